@@ -0,0 +1,338 @@
+use serde::{de::DeserializeOwned, ser::Serialize};
+use std::marker::PhantomData;
+
+#[cfg(feature = "iterator")]
+use cosmwasm_std::Order;
+use cosmwasm_std::{ReadonlyStorage, Storage};
+
+use crate::bucket::{Bucket, ReadonlyBucket};
+#[cfg(feature = "iterator")]
+use crate::codec::Json;
+use crate::errors::{Result, StorageError};
+#[cfg(feature = "iterator")]
+use crate::keys::PrimaryKey;
+use crate::namespace_helpers::try_key_prefix;
+#[cfg(feature = "iterator")]
+use crate::namespace_helpers::{key_prefix_nested, range_with_prefix};
+use crate::prefix::{PrefixedStorage, ReadonlyPrefixedStorage};
+use crate::sequence::{currval, nextval, sequence, sequence_read};
+#[cfg(feature = "iterator")]
+use crate::type_helpers::deserialize_kv;
+
+const SEQUENCE_KEY: &[u8] = b"seq";
+const DATA_NAMESPACE: &[u8] = b"data";
+
+pub fn auto_increment_bucket<'a, S: Storage, T>(
+    namespace: &[u8],
+    storage: &'a mut S,
+) -> AutoIncrementBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    AutoIncrementBucket::new(namespace, storage)
+}
+
+pub fn auto_increment_bucket_read<'a, S: ReadonlyStorage, T>(
+    namespace: &[u8],
+    storage: &'a S,
+) -> ReadonlyAutoIncrementBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    ReadonlyAutoIncrementBucket::new(namespace, storage)
+}
+
+/// AutoIncrementBucket stores items under ids assigned from a sequence.
+///
+/// The sequence and the items live in the same namespace, so `push` only needs one mutable
+/// borrow of the storage. Ids start at 1 and are stored big endian, so iteration returns
+/// the items in insertion order.
+pub struct AutoIncrementBucket<'a, S: Storage, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    storage: &'a mut S,
+    namespace: Vec<u8>,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+}
+
+impl<'a, S: Storage, T> AutoIncrementBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(namespace: &[u8], storage: &'a mut S) -> Self {
+        AutoIncrementBucket {
+            storage,
+            namespace: namespace.to_vec(),
+            data: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a mut S) -> Result<Self> {
+        try_key_prefix(namespace)?;
+        Ok(Self::new(namespace, storage))
+    }
+
+    /// push stores the item under the next id from the sequence and returns the id
+    pub fn push(&mut self, data: &T) -> Result<u64> {
+        let id = {
+            let mut store = PrefixedStorage::new(&self.namespace, self.storage);
+            nextval(&mut sequence(&mut store, SEQUENCE_KEY))?
+        };
+        self.items().save(&id.to_be_bytes(), data)?;
+        Ok(id)
+    }
+
+    /// save overwrites the item with an id previously returned by push
+    pub fn save(&mut self, id: u64, data: &T) -> Result<()> {
+        self.items().save(&id.to_be_bytes(), data)
+    }
+
+    /// remove deletes the item. The id is never handed out again
+    pub fn remove(&mut self, id: u64) -> Result<()> {
+        self.items().remove(&id.to_be_bytes())
+    }
+
+    /// load will return an error if there is no item with the given id, or on parse error
+    pub fn load(&self, id: u64) -> Result<T> {
+        self.items_read().load(&id.to_be_bytes())
+    }
+
+    /// may_load returns Ok(None) if there is no item with the given id
+    pub fn may_load(&self, id: u64) -> Result<Option<T>> {
+        self.items_read().may_load(&id.to_be_bytes())
+    }
+
+    /// update will load the item, perform the specified action, and store the result.
+    /// See Bucket::update
+    pub fn update<A, E>(&mut self, id: u64, action: A) -> Result<T, E>
+    where
+        A: FnOnce(T) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        self.items().update(&id.to_be_bytes(), action)
+    }
+
+    /// last_id returns the id assigned by the latest push, or 0 if nothing was pushed yet
+    pub fn last_id(&self) -> Result<u64> {
+        last_id(self.storage, &self.namespace)
+    }
+
+    /// range iterates over all items with start <= id < end (unbounded if None) in the given order
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        start: Option<u64>,
+        end: Option<u64>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(u64, T)>> + 'b> {
+        range_by_id(self.storage, &self.namespace, start, end, order)
+    }
+
+    fn items(&mut self) -> Bucket<'_, S, T> {
+        Bucket::multilevel(&[&self.namespace, DATA_NAMESPACE], self.storage)
+    }
+
+    fn items_read(&self) -> ReadonlyBucket<'_, S, T> {
+        ReadonlyBucket::multilevel(&[&self.namespace, DATA_NAMESPACE], self.storage)
+    }
+}
+
+/// ReadonlyAutoIncrementBucket only requires a ReadonlyStorage and exposes only the
+/// methods of AutoIncrementBucket that don't modify state.
+pub struct ReadonlyAutoIncrementBucket<'a, S: ReadonlyStorage, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    storage: &'a S,
+    namespace: Vec<u8>,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+}
+
+impl<'a, S: ReadonlyStorage, T> ReadonlyAutoIncrementBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(namespace: &[u8], storage: &'a S) -> Self {
+        ReadonlyAutoIncrementBucket {
+            storage,
+            namespace: namespace.to_vec(),
+            data: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a S) -> Result<Self> {
+        try_key_prefix(namespace)?;
+        Ok(Self::new(namespace, storage))
+    }
+
+    /// load will return an error if there is no item with the given id, or on parse error
+    pub fn load(&self, id: u64) -> Result<T> {
+        self.items().load(&id.to_be_bytes())
+    }
+
+    /// may_load returns Ok(None) if there is no item with the given id
+    pub fn may_load(&self, id: u64) -> Result<Option<T>> {
+        self.items().may_load(&id.to_be_bytes())
+    }
+
+    /// last_id returns the id assigned by the latest push, or 0 if nothing was pushed yet
+    pub fn last_id(&self) -> Result<u64> {
+        last_id(self.storage, &self.namespace)
+    }
+
+    /// range iterates over all items with start <= id < end (unbounded if None) in the given order
+    #[cfg(feature = "iterator")]
+    pub fn range(
+        &self,
+        start: Option<u64>,
+        end: Option<u64>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(u64, T)>> + 'a> {
+        range_by_id(self.storage, &self.namespace, start, end, order)
+    }
+
+    fn items(&self) -> ReadonlyBucket<'a, S, T> {
+        ReadonlyBucket::multilevel(&[&self.namespace, DATA_NAMESPACE], self.storage)
+    }
+}
+
+// last_id reads the current value of the sequence
+fn last_id<S: ReadonlyStorage>(storage: &S, namespace: &[u8]) -> Result<u64> {
+    let store = ReadonlyPrefixedStorage::new(namespace, storage);
+    currval(&sequence_read(&store, SEQUENCE_KEY))
+}
+
+#[cfg(feature = "iterator")]
+fn range_by_id<'a, S: ReadonlyStorage, T>(
+    storage: &'a S,
+    namespace: &[u8],
+    start: Option<u64>,
+    end: Option<u64>,
+    order: Order,
+) -> Box<dyn Iterator<Item = Result<(u64, T)>> + 'a>
+where
+    T: Serialize + DeserializeOwned + 'a,
+{
+    let prefix = key_prefix_nested(&[namespace, DATA_NAMESPACE]);
+    let start = start.map(|id| id.to_be_bytes());
+    let end = end.map(|id| id.to_be_bytes());
+    let base = range_with_prefix(
+        storage,
+        &prefix,
+        start.as_ref().map(|s| &s[..]),
+        end.as_ref().map(|e| &e[..]),
+        order,
+    );
+    let mapped = base.map(move |kv| {
+        let (k, v) = deserialize_kv::<Json, T>(&prefix, kv)?;
+        Ok((u64::parse_key(&k)?, v))
+    });
+    Box::new(mapped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct Withdrawal {
+        pub recipient: String,
+        pub amount: u64,
+    }
+
+    fn withdrawal(recipient: &str, amount: u64) -> Withdrawal {
+        Withdrawal {
+            recipient: recipient.to_string(),
+            amount,
+        }
+    }
+
+    #[test]
+    fn push_assigns_ids() {
+        let mut store = MockStorage::new();
+        let mut bucket = auto_increment_bucket::<_, Withdrawal>(b"withdrawals", &mut store);
+        assert_eq!(bucket.last_id().unwrap(), 0);
+
+        let first = bucket.push(&withdrawal("alice", 100)).unwrap();
+        let second = bucket.push(&withdrawal("bob", 200)).unwrap();
+        assert_eq!(first, 1);
+        assert_eq!(second, 2);
+        assert_eq!(bucket.last_id().unwrap(), 2);
+
+        assert_eq!(bucket.load(1).unwrap(), withdrawal("alice", 100));
+        assert_eq!(bucket.load(2).unwrap(), withdrawal("bob", 200));
+        assert!(bucket.load(3).is_err());
+        assert_eq!(bucket.may_load(3).unwrap(), None);
+    }
+
+    #[test]
+    fn ids_are_not_reused() {
+        let mut store = MockStorage::new();
+        let mut bucket = auto_increment_bucket::<_, Withdrawal>(b"withdrawals", &mut store);
+
+        bucket.push(&withdrawal("alice", 100)).unwrap();
+        bucket.push(&withdrawal("bob", 200)).unwrap();
+        bucket.remove(2).unwrap();
+        assert_eq!(bucket.may_load(2).unwrap(), None);
+        assert_eq!(bucket.push(&withdrawal("carl", 300)).unwrap(), 3);
+
+        let updated = bucket
+            .update(1, |mut w| -> Result<_> {
+                w.amount += 1;
+                Ok(w)
+            })
+            .unwrap();
+        assert_eq!(updated.amount, 101);
+        bucket.save(3, &withdrawal("carl", 5)).unwrap();
+        assert_eq!(bucket.load(3).unwrap().amount, 5);
+    }
+
+    #[test]
+    fn buckets_are_isolated() {
+        let mut store = MockStorage::new();
+        auto_increment_bucket::<_, Withdrawal>(b"withdrawals", &mut store)
+            .push(&withdrawal("alice", 100))
+            .unwrap();
+        let id = auto_increment_bucket::<_, Withdrawal>(b"other", &mut store)
+            .push(&withdrawal("bob", 100))
+            .unwrap();
+        assert_eq!(id, 1);
+
+        let reader = auto_increment_bucket_read::<_, Withdrawal>(b"withdrawals", &store);
+        assert_eq!(reader.last_id().unwrap(), 1);
+        assert_eq!(reader.load(1).unwrap().recipient, "alice");
+        assert_eq!(reader.may_load(2).unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_in_id_order() {
+        let mut store = MockStorage::new();
+        let mut bucket = auto_increment_bucket::<_, Withdrawal>(b"withdrawals", &mut store);
+        // enough items to make sure ids are not sorted as strings
+        for i in 0..12 {
+            bucket.push(&withdrawal("alice", i)).unwrap();
+        }
+
+        let ids: Result<Vec<u64>> = bucket
+            .range(None, None, Order::Ascending)
+            .map(|item| item.map(|(id, _)| id))
+            .collect();
+        assert_eq!(ids.unwrap(), (1..=12).collect::<Vec<u64>>());
+
+        let reader = auto_increment_bucket_read::<_, Withdrawal>(b"withdrawals", &store);
+        let some: Result<Vec<(u64, Withdrawal)>> =
+            reader.range(Some(9), Some(11), Order::Descending).collect();
+        assert_eq!(
+            some.unwrap(),
+            vec![(10, withdrawal("alice", 9)), (9, withdrawal("alice", 8))]
+        );
+    }
+}
@@ -1,11 +1,26 @@
 use serde::{de::DeserializeOwned, ser::Serialize};
 use std::marker::PhantomData;
 
-use cosmwasm::errors::Result;
-use cosmwasm::traits::{ReadonlyStorage, Storage};
+#[cfg(feature = "iterator")]
+use cosmwasm_std::Order;
+use cosmwasm_std::{ReadonlyStorage, Storage};
 
-use crate::namespace_helpers::{get_with_prefix, key_prefix, key_prefix_nested, set_with_prefix};
-use crate::type_helpers::{may_deserialize, must_deserialize, serialize};
+use crate::cache::{may_load_cached, must_load_cached, TypedCache};
+use crate::codec::{Codec, Json};
+#[cfg(feature = "iterator")]
+use crate::errors::InvalidArgument;
+use crate::errors::{Result, StorageError};
+#[cfg(feature = "iterator")]
+use crate::namespace_helpers::range_with_prefix;
+use crate::namespace_helpers::{
+    get_with_prefix, key_prefix, key_prefix_nested, remove_with_prefix, set_with_prefix,
+    try_key_prefix, try_key_prefix_nested,
+};
+#[cfg(feature = "iterator")]
+use crate::pagination::{collect_page, range_after, Page, Pagination};
+#[cfg(feature = "iterator")]
+use crate::type_helpers::deserialize_kv;
+use crate::type_helpers::{may_deserialize, must_deserialize};
 
 pub fn bucket<'a, S: Storage, T>(namespace: &[u8], storage: &'a mut S) -> Bucket<'a, S, T>
 where
@@ -24,25 +39,29 @@ where
     ReadonlyBucket::new(namespace, storage)
 }
 
-pub struct Bucket<'a, S: Storage, T>
+pub struct Bucket<'a, S: Storage, T, C = Json>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     storage: &'a mut S,
     // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
     data: PhantomData<&'a T>,
+    codec: PhantomData<C>,
     prefix: Vec<u8>,
 }
 
-impl<'a, S: Storage, T> Bucket<'a, S, T>
+impl<'a, S: Storage, T, C> Bucket<'a, S, T, C>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     pub fn new(namespace: &[u8], storage: &'a mut S) -> Self {
         Bucket {
             prefix: key_prefix(namespace),
             storage,
             data: PhantomData,
+            codec: PhantomData,
         }
     }
 
@@ -51,61 +70,259 @@ where
             prefix: key_prefix_nested(namespaces),
             storage,
             data: PhantomData,
+            codec: PhantomData,
         }
     }
 
+    /// try_new works like new, but returns an error instead of panicking if the namespace
+    /// is longer than 0xFFFF bytes. Use it for namespaces derived from user input
+    pub fn try_new(namespace: &[u8], storage: &'a mut S) -> Result<Self> {
+        Ok(Bucket {
+            prefix: try_key_prefix(namespace)?,
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
+    /// try_multilevel works like multilevel, but returns an error instead of panicking
+    /// if any namespace is longer than 0xFFFF bytes
+    pub fn try_multilevel(namespaces: &[&[u8]], storage: &'a mut S) -> Result<Self> {
+        Ok(Bucket {
+            prefix: try_key_prefix_nested(namespaces)?,
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
+    // sub_bucket returns a Bucket over all keys starting with the given raw key prefix.
+    // The caller must ensure the key prefix is length-prefixed, so it cannot collide with other keys
+    pub(crate) fn sub_bucket(&mut self, key_prefix: &[u8]) -> Bucket<'_, S, T, C> {
+        let mut prefix = self.prefix.clone();
+        prefix.extend_from_slice(key_prefix);
+        Bucket {
+            prefix,
+            storage: self.storage,
+            data: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    // storage gives wrappers access to the underlying storage, e.g. to maintain indexes next to
+    // the data of the bucket
+    pub(crate) fn storage(&self) -> &S {
+        self.storage
+    }
+
+    pub(crate) fn storage_mut(&mut self) -> &mut S {
+        self.storage
+    }
+
     /// save will serialize the model and store, returns an error on serialization issues
     pub fn save(&mut self, key: &[u8], data: &T) -> Result<()> {
-        set_with_prefix(self.storage, &self.prefix, key, &serialize(data)?);
+        set_with_prefix(self.storage, &self.prefix, key, &C::encode(data)?)?;
+        Ok(())
+    }
+
+    /// remove will delete the data stored at the key. Removing a missing key is a no-op
+    pub fn remove(&mut self, key: &[u8]) -> Result<()> {
+        remove_with_prefix(self.storage, &self.prefix, key)?;
         Ok(())
     }
 
     /// load will return an error if no data is set at the given key, or on parse error
     pub fn load(&self, key: &[u8]) -> Result<T> {
-        let value = get_with_prefix(self.storage, &self.prefix, key);
-        must_deserialize(&value)
+        let value = get_with_prefix(self.storage, &self.prefix, key)?;
+        must_deserialize::<C, _>(&self.prefix, key, &value)
     }
 
     /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
     /// returns an error on issues parsing
     pub fn may_load(&self, key: &[u8]) -> Result<Option<T>> {
-        let value = get_with_prefix(self.storage, &self.prefix, key);
-        may_deserialize(&value)
+        let value = get_with_prefix(self.storage, &self.prefix, key)?;
+        may_deserialize::<C, _>(&self.prefix, key, &value)
+    }
+
+    /// range iterates over all entries in the bucket with start <= key < end (unbounded if None)
+    /// in the given order. Keys are returned without the bucket prefix and values are only
+    /// parsed when the iterator reaches them
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'b> {
+        let prefix = &self.prefix;
+        let mapped = range_with_prefix(self.storage, prefix, start, end, order)
+            .map(move |kv| deserialize_kv::<C, T>(prefix, kv));
+        Box::new(mapped)
+    }
+
+    /// page returns up to limit entries after the start_after key (exclusive, from the first key
+    /// if None) in the given order. The limit defaults to and is capped by the pagination settings.
+    /// next_start_after of the page is the key to continue with, None if there are no more entries
+    #[cfg(feature = "iterator")]
+    pub fn page(
+        &self,
+        start_after: Option<&[u8]>,
+        limit: Option<u32>,
+        order: Order,
+        pagination: &Pagination,
+    ) -> Result<Page<Vec<u8>, T>> {
+        let (start, end) = range_after(start_after, order);
+        let items = self.range(
+            start.as_ref().map(|k| k.as_slice()),
+            end.as_ref().map(|k| k.as_slice()),
+            order,
+        );
+        collect_page(items, pagination.limit(limit))
     }
 
     /// update will load the data, perform the specified action, and store the result
     /// in the database. This is shorthand for some common sequences, which may be useful.
-    /// Note that this only updates *pre-existing* values. If you want to modify possibly
-    /// non-existent values, please use `may_update`
+    /// Note that this only updates *pre-existing* values and returns NotFound otherwise.
+    /// If you want to modify possibly non-existent values, please use `may_update`
     ///
-    /// This is the least stable of the APIs, and definitely needs some usage
-    pub fn update(&mut self, key: &[u8], action: &dyn Fn(Option<T>) -> Result<T>) -> Result<T> {
+    /// The action may return any error type that a StorageError can be converted into
+    pub fn update<A, E>(&mut self, key: &[u8], action: A) -> Result<T, E>
+    where
+        A: FnOnce(T) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.load(key)?;
+        let output = action(input)?;
+        self.save(key, &output)?;
+        Ok(output)
+    }
+
+    /// may_update works like update, but also calls the action if there is no data
+    /// stored at the key, passing None. This allows creating new values
+    pub fn may_update<A, E>(&mut self, key: &[u8], action: A) -> Result<T, E>
+    where
+        A: FnOnce(Option<T>) -> Result<T, E>,
+        E: From<StorageError>,
+    {
         let input = self.may_load(key)?;
         let output = action(input)?;
         self.save(key, &output)?;
         Ok(output)
     }
+
+    /// migrate_to transforms the entries of this bucket for a contract migration. It processes up
+    /// to limit entries in key order, starting after the start_after key (from the beginning if None).
+    ///
+    /// The action receives every key and value and returns the new key and value to store in the
+    /// bucket with the target namespaces (which may be the namespace of this bucket), or None to
+    /// delete the entry. Entries are removed from this bucket unless they are written to the same key.
+    ///
+    /// Returns the last processed key if the limit was reached, pass it as start_after to continue
+    /// in the next batch (possibly in a later transaction), or None once all entries are migrated.
+    /// The limit must be at least 1.
+    /// When migrating within the same namespace, the new keys must sort before or equal to the old
+    /// ones, otherwise they are visited again by later batches.
+    #[cfg(feature = "iterator")]
+    pub fn migrate_to<N, A, E>(
+        &mut self,
+        target: &[&[u8]],
+        start_after: Option<&[u8]>,
+        limit: u32,
+        mut action: A,
+    ) -> Result<Option<Vec<u8>>, E>
+    where
+        N: Serialize,
+        A: FnMut(Vec<u8>, T) -> Result<Option<(Vec<u8>, N)>, E>,
+        E: From<StorageError>,
+    {
+        if limit == 0 {
+            // a batch without entries could not tell if the migration is done
+            return InvalidArgument {
+                msg: "migration limit must be at least 1",
+            }
+            .fail()
+            .map_err(E::from);
+        }
+        // the first key after start_after
+        let start = start_after.map(|k| [k, b"\x00"].concat());
+        let batch: Vec<_> = range_with_prefix(
+            self.storage,
+            &self.prefix,
+            start.as_ref().map(|s| &s[..]),
+            None,
+            Order::Ascending,
+        )
+        .take(limit as usize)
+        .collect();
+        let done = batch.len() < limit as usize;
+
+        let target_prefix = key_prefix_nested(target);
+        let mut last = None;
+        for kv in batch {
+            let (key, old) = deserialize_kv::<C, T>(&self.prefix, kv)?;
+            match action(key.clone(), old)? {
+                Some((new_key, new)) => {
+                    // encode first, so the old entry is kept on serialization errors
+                    let value = C::encode(&new)?;
+                    if target_prefix != self.prefix || new_key != key {
+                        remove_with_prefix(self.storage, &self.prefix, &key)
+                            .map_err(StorageError::from)?;
+                    }
+                    set_with_prefix(self.storage, &target_prefix, &new_key, &value)
+                        .map_err(StorageError::from)?;
+                }
+                None => {
+                    remove_with_prefix(self.storage, &self.prefix, &key)
+                        .map_err(StorageError::from)?;
+                }
+            }
+            last = Some(key);
+        }
+        Ok(if done { None } else { last })
+    }
 }
 
-pub struct ReadonlyBucket<'a, S: ReadonlyStorage, T>
+impl<'a, S, T, C> Bucket<'a, S, T, C>
+where
+    S: Storage + TypedCache,
+    T: Serialize + DeserializeOwned + Clone + 'static,
+    C: Codec + 'static,
+{
+    /// load_cached works like load, but memoizes the parsed value in the storage (see CachedStorage),
+    /// so loading the same key again returns a clone without parsing
+    pub fn load_cached(&self, key: &[u8]) -> Result<T> {
+        must_load_cached::<_, C, T>(self.storage, &self.prefix, key)
+    }
+
+    /// may_load_cached works like may_load, but memoizes the parsed value in the storage
+    pub fn may_load_cached(&self, key: &[u8]) -> Result<Option<T>> {
+        may_load_cached::<_, C, T>(self.storage, &self.prefix, key)
+    }
+}
+
+pub struct ReadonlyBucket<'a, S: ReadonlyStorage, T, C = Json>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     storage: &'a S,
     // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
     data: PhantomData<&'a T>,
+    codec: PhantomData<C>,
     prefix: Vec<u8>,
 }
 
-impl<'a, S: ReadonlyStorage, T> ReadonlyBucket<'a, S, T>
+impl<'a, S: ReadonlyStorage, T, C> ReadonlyBucket<'a, S, T, C>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     pub fn new(namespace: &[u8], storage: &'a S) -> Self {
         ReadonlyBucket {
             prefix: key_prefix(namespace),
             storage,
             data: PhantomData,
+            codec: PhantomData,
         }
     }
 
@@ -114,30 +331,124 @@ where
             prefix: key_prefix_nested(namespaces),
             storage,
             data: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error instead of panicking if the namespace
+    /// is longer than 0xFFFF bytes. Use it for namespaces derived from user input
+    pub fn try_new(namespace: &[u8], storage: &'a S) -> Result<Self> {
+        Ok(ReadonlyBucket {
+            prefix: try_key_prefix(namespace)?,
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
+    /// try_multilevel works like multilevel, but returns an error instead of panicking
+    /// if any namespace is longer than 0xFFFF bytes
+    pub fn try_multilevel(namespaces: &[&[u8]], storage: &'a S) -> Result<Self> {
+        Ok(ReadonlyBucket {
+            prefix: try_key_prefix_nested(namespaces)?,
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
+    // sub_bucket returns a ReadonlyBucket over all keys starting with the given raw key prefix.
+    // The caller must ensure the key prefix is length-prefixed, so it cannot collide with other keys
+    pub(crate) fn sub_bucket(&self, key_prefix: &[u8]) -> ReadonlyBucket<'a, S, T, C> {
+        let mut prefix = self.prefix.clone();
+        prefix.extend_from_slice(key_prefix);
+        ReadonlyBucket {
+            prefix,
+            storage: self.storage,
+            data: PhantomData,
+            codec: PhantomData,
         }
     }
 
+    // storage gives wrappers access to the underlying storage, see Bucket::storage
+    pub(crate) fn storage(&self) -> &'a S {
+        self.storage
+    }
+
     /// load will return an error if no data is set at the given key, or on parse error
     pub fn load(&self, key: &[u8]) -> Result<T> {
-        let value = get_with_prefix(self.storage, &self.prefix, key);
-        must_deserialize(&value)
+        let value = get_with_prefix(self.storage, &self.prefix, key)?;
+        must_deserialize::<C, _>(&self.prefix, key, &value)
     }
 
     /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
     /// returns an error on issues parsing
     pub fn may_load(&self, key: &[u8]) -> Result<Option<T>> {
-        let value = get_with_prefix(self.storage, &self.prefix, key);
-        may_deserialize(&value)
+        let value = get_with_prefix(self.storage, &self.prefix, key)?;
+        may_deserialize::<C, _>(&self.prefix, key, &value)
+    }
+
+    /// range iterates over all entries in the bucket with start <= key < end (unbounded if None)
+    /// in the given order. Keys are returned without the bucket prefix and values are only
+    /// parsed when the iterator reaches them
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'b> {
+        let prefix = &self.prefix;
+        let mapped = range_with_prefix(self.storage, prefix, start, end, order)
+            .map(move |kv| deserialize_kv::<C, T>(prefix, kv));
+        Box::new(mapped)
+    }
+
+    /// page returns up to limit entries after the start_after key (exclusive, from the first key
+    /// if None) in the given order. The limit defaults to and is capped by the pagination settings.
+    /// next_start_after of the page is the key to continue with, None if there are no more entries
+    #[cfg(feature = "iterator")]
+    pub fn page(
+        &self,
+        start_after: Option<&[u8]>,
+        limit: Option<u32>,
+        order: Order,
+        pagination: &Pagination,
+    ) -> Result<Page<Vec<u8>, T>> {
+        let (start, end) = range_after(start_after, order);
+        let items = self.range(
+            start.as_ref().map(|k| k.as_slice()),
+            end.as_ref().map(|k| k.as_slice()),
+            order,
+        );
+        collect_page(items, pagination.limit(limit))
+    }
+}
+
+impl<'a, S, T, C> ReadonlyBucket<'a, S, T, C>
+where
+    S: ReadonlyStorage + TypedCache,
+    T: Serialize + DeserializeOwned + Clone + 'static,
+    C: Codec + 'static,
+{
+    /// load_cached works like load, but memoizes the parsed value in the storage (see CachedStorage),
+    /// so loading the same key again returns a clone without parsing
+    pub fn load_cached(&self, key: &[u8]) -> Result<T> {
+        must_load_cached::<_, C, T>(self.storage, &self.prefix, key)
+    }
+
+    /// may_load_cached works like may_load, but memoizes the parsed value in the storage
+    pub fn may_load_cached(&self, key: &[u8]) -> Result<Option<T>> {
+        may_load_cached::<_, C, T>(self.storage, &self.prefix, key)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use cosmwasm::errors::{contract_err, NotFound};
-    use cosmwasm::mock::MockStorage;
+    use cosmwasm_std::generic_err;
+    use cosmwasm_std::testing::MockStorage;
     use serde::{Deserialize, Serialize};
-    use snafu::OptionExt;
 
     #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
     struct Data {
@@ -145,6 +456,28 @@ mod test {
         pub age: i32,
     }
 
+    #[test]
+    fn try_new_rejects_long_namespace() {
+        let mut store = MockStorage::new();
+        let long_namespace = vec![b'a'; 0x10000];
+        match Bucket::<_, u32>::try_new(&long_namespace, &mut store) {
+            Err(StorageError::NamespaceTooLong { length }) => assert_eq!(length, 0x10000),
+            _ => panic!("expected NamespaceTooLong"),
+        }
+        let nested: &[&[u8]] = &[b"data", &long_namespace];
+        assert!(Bucket::<_, u32>::try_multilevel(nested, &mut store).is_err());
+        assert!(ReadonlyBucket::<_, u32>::try_multilevel(nested, &store).is_err());
+
+        Bucket::<_, u32>::try_multilevel(&[b"data", b"2020"], &mut store)
+            .unwrap()
+            .save(b"maria", &42)
+            .unwrap();
+        let reader = ReadonlyBucket::<_, u32>::multilevel(&[b"data", b"2020"], &store);
+        assert_eq!(reader.load(b"maria").unwrap(), 42);
+        let reader = ReadonlyBucket::<_, u32>::try_new(b"data", &store).unwrap();
+        assert_eq!(reader.may_load(b"maria").unwrap(), None);
+    }
+
     #[test]
     fn store_and_load() {
         let mut store = MockStorage::new();
@@ -162,6 +495,46 @@ mod test {
         assert_eq!(data, loaded);
     }
 
+    #[test]
+    fn default_codec_is_json() {
+        let mut store = MockStorage::new();
+        let mut bucket = bucket::<_, Data>(b"data", &mut store);
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        bucket.save(b"maria", &data).unwrap();
+
+        let raw = crate::prefixed_read(b"data", &store).get(b"maria").unwrap();
+        assert_eq!(raw.unwrap(), br#"{"name":"Maria","age":42}"#.to_vec());
+    }
+
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn custom_codec() {
+        use crate::{Bincode, CompactBincode};
+
+        let mut store = MockStorage::new();
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        let mut fixed = Bucket::<_, Data, Bincode>::new(b"fixed", &mut store);
+        fixed.save(b"maria", &data).unwrap();
+        assert_eq!(fixed.load(b"maria").unwrap(), data);
+        let mut compact = Bucket::<_, Data, CompactBincode>::multilevel(&[b"compact"], &mut store);
+        compact.save(b"maria", &data).unwrap();
+        assert_eq!(compact.load(b"maria").unwrap(), data);
+
+        let reader = ReadonlyBucket::<_, Data, Bincode>::new(b"fixed", &store);
+        assert_eq!(reader.load(b"maria").unwrap(), data);
+        assert_eq!(reader.may_load(b"john").unwrap(), None);
+
+        // the codec must match to read the data
+        let json = bucket_read::<_, Data>(b"fixed", &store);
+        assert!(json.load(b"maria").is_err());
+    }
+
     #[test]
     fn readonly_works() {
         let mut store = MockStorage::new();
@@ -174,7 +547,7 @@ mod test {
         };
         bucket.save(b"maria", &data).unwrap();
 
-        let reader = bucket_read::<_, Data>(b"data", &mut store);
+        let reader = bucket_read::<_, Data>(b"data", &store);
 
         // check empty data handling
         assert!(reader.load(b"john").is_err());
@@ -185,6 +558,127 @@ mod test {
         assert_eq!(data, loaded);
     }
 
+    #[test]
+    fn remove_works() {
+        let mut store = MockStorage::new();
+        let mut bucket = bucket::<_, Data>(b"data", &mut store);
+
+        // save data
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        bucket.save(b"maria", &data).unwrap();
+        assert_eq!(data, bucket.load(b"maria").unwrap());
+
+        // now delete it
+        bucket.remove(b"maria").unwrap();
+        assert_eq!(None, bucket.may_load(b"maria").unwrap());
+        assert!(bucket.load(b"maria").is_err());
+    }
+
+    #[test]
+    fn remove_isolated() {
+        let mut store = MockStorage::new();
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+
+        // (data, maria) vs (dat, amaria)
+        bucket::<_, Data>(b"data", &mut store)
+            .save(b"maria", &data)
+            .unwrap();
+        bucket::<_, Data>(b"dat", &mut store)
+            .save(b"amaria", &data)
+            .unwrap();
+
+        bucket::<_, Data>(b"data", &mut store)
+            .remove(b"maria")
+            .unwrap();
+
+        let reader = bucket_read::<_, Data>(b"data", &store);
+        assert_eq!(None, reader.may_load(b"maria").unwrap());
+        let reader2 = bucket_read::<_, Data>(b"dat", &store);
+        assert_eq!(data, reader2.load(b"amaria").unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_over_data() {
+        let mut store = MockStorage::new();
+        let mut bucket = bucket::<_, Data>(b"data", &mut store);
+
+        let jose = Data {
+            name: "Jose".to_string(),
+            age: 42,
+        };
+        let maria = Data {
+            name: "Maria".to_string(),
+            age: 27,
+        };
+
+        bucket.save(b"maria", &maria).unwrap();
+        bucket.save(b"jose", &jose).unwrap();
+
+        // data in a neighbouring namespace must not show up
+        let mut other = Bucket::<_, Data>::new(b"dat", &mut store);
+        other.save(b"amaria", &maria).unwrap();
+
+        let reader = bucket_read::<_, Data>(b"data", &store);
+
+        // ensure we get proper result from the range iterator
+        let res_data: Result<Vec<(Vec<u8>, Data)>> =
+            reader.range(None, None, Order::Ascending).collect();
+        let data = res_data.unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0], (b"jose".to_vec(), jose.clone()));
+        assert_eq!(data[1], (b"maria".to_vec(), maria.clone()));
+
+        // also works in reverse
+        let res_data: Result<Vec<(Vec<u8>, Data)>> =
+            reader.range(None, None, Order::Descending).collect();
+        let data = res_data.unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0], (b"maria".to_vec(), maria.clone()));
+        assert_eq!(data[1], (b"jose".to_vec(), jose.clone()));
+
+        // check start and end bounds
+        let res_data: Result<Vec<(Vec<u8>, Data)>> =
+            reader.range(Some(b"k"), None, Order::Ascending).collect();
+        assert_eq!(res_data.unwrap(), vec![(b"maria".to_vec(), maria)]);
+        let res_data: Result<Vec<(Vec<u8>, Data)>> = reader
+            .range(None, Some(b"maria"), Order::Descending)
+            .collect();
+        assert_eq!(res_data.unwrap(), vec![(b"jose".to_vec(), jose)]);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_is_lazy() {
+        let mut store = MockStorage::new();
+        let maria = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        bucket::<_, Data>(b"data", &mut store)
+            .save(b"maria", &maria)
+            .unwrap();
+        store
+            .set(
+                &[key_prefix(b"data"), b"zzz".to_vec()].concat(),
+                b"invalid json",
+            )
+            .unwrap();
+
+        // parse errors only show up when the broken entry is reached
+        let bucket = bucket_read::<_, Data>(b"data", &store);
+        let mut iter = bucket.range(None, None, Order::Ascending);
+        assert_eq!(iter.next().unwrap().unwrap(), (b"maria".to_vec(), maria));
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+
     #[test]
     fn buckets_isolated() {
         let mut store = MockStorage::new();
@@ -234,12 +728,11 @@ mod test {
         bucket.save(b"maria", &init).unwrap();
 
         // it's my birthday
-        let birthday = |mayd: Option<Data>| -> Result<Data> {
-            let mut d = mayd.context(NotFound { kind: "Data" })?;
+        let birthday = |mut d: Data| -> Result<Data> {
             d.age += 1;
             Ok(d)
         };
-        let output = bucket.update(b"maria", &birthday).unwrap();
+        let output = bucket.update(b"maria", birthday).unwrap();
         let expected = Data {
             name: "Maria".to_string(),
             age: 43,
@@ -264,7 +757,7 @@ mod test {
         bucket.save(b"maria", &init).unwrap();
 
         // it's my birthday
-        let output = bucket.update(b"maria", &|_d| contract_err("cuz i feel like it"));
+        let output = bucket.update(b"maria", |_d| Err(generic_err("cuz i feel like it")));
         assert!(output.is_err());
 
         // load it properly
@@ -284,8 +777,8 @@ mod test {
 
         // it's my birthday
         let output = bucket
-            .update(b"maria", &|d| match d {
-                Some(_) => contract_err("Ensure this was empty"),
+            .may_update(b"maria", |d| match d {
+                Some(_) => Err(generic_err("Ensure this was empty")),
                 None => Ok(init_value.clone()),
             })
             .unwrap();
@@ -295,4 +788,237 @@ mod test {
         let loaded = bucket.load(b"maria").unwrap();
         assert_eq!(loaded, init_value);
     }
+
+    #[test]
+    fn update_requires_data() {
+        let mut store = MockStorage::new();
+        let mut bucket = bucket::<_, Data>(b"data", &mut store);
+
+        let output = bucket.update(b"maria", Ok);
+        match output {
+            Err(StorageError::NotFound { .. }) => {}
+            _ => panic!("Unexpected output: {:?}", output),
+        }
+        assert_eq!(bucket.may_load(b"maria").unwrap(), None);
+    }
+
+    #[test]
+    fn errors_show_location() {
+        let mut store = MockStorage::new();
+        let bad_key = [key_prefix_nested(&[b"data", b"2020"]), b"bad".to_vec()].concat();
+        store.set(&bad_key, b"not json").unwrap();
+
+        let bucket = ReadonlyBucket::<_, Data>::multilevel(&[b"data", b"2020"], &store);
+        let err = bucket.load(b"maria").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cw_storage::bucket::test::Data not found in data/2020 at maria"
+        );
+        match bucket.load(b"bad").unwrap_err() {
+            StorageError::ParseErr { namespace, key, .. } => {
+                assert_eq!(namespace, key_prefix_nested(&[b"data", b"2020"]));
+                assert_eq!(key, b"bad".to_vec());
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+        assert!(bucket.may_load(b"bad").is_err());
+    }
+
+    #[test]
+    fn update_moves_captured_values() {
+        let mut store = MockStorage::new();
+        let mut bucket = bucket::<_, Data>(b"data", &mut store);
+
+        let name = "Maria".to_string();
+        let output = bucket
+            .may_update(b"maria", move |_| -> Result<Data> {
+                Ok(Data { name, age: 42 })
+            })
+            .unwrap();
+        assert_eq!(output.name, "Maria");
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum MyError {
+        Std(String),
+        TooOld(i32),
+    }
+
+    impl From<StorageError> for MyError {
+        fn from(err: StorageError) -> Self {
+            MyError::Std(err.to_string())
+        }
+    }
+
+    #[test]
+    fn update_custom_error() {
+        let mut store = MockStorage::new();
+        let mut bucket = bucket::<_, Data>(b"data", &mut store);
+
+        let init = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        bucket.save(b"maria", &init).unwrap();
+
+        let output = bucket.update(b"maria", |d| {
+            if d.age > 40 {
+                return Err(MyError::TooOld(d.age));
+            }
+            Ok(d)
+        });
+        assert_eq!(output, Err(MyError::TooOld(42)));
+
+        // errors from the bucket are converted
+        let output = bucket.update(b"john", Ok::<_, MyError>);
+        match output {
+            Err(MyError::Std(_)) => {}
+            _ => panic!("Unexpected output: {:?}", output),
+        }
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[cfg(feature = "iterator")]
+    struct Person {
+        pub first: String,
+        pub last: String,
+        pub age: i32,
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn migrate_in_batches() {
+        let mut store = MockStorage::new();
+        let mut bucket = bucket::<_, Data>(b"people", &mut store);
+        for (name, age) in &[
+            ("anna", 30),
+            ("bert", 31),
+            ("carl", 32),
+            ("dora", 33),
+            ("emil", 34),
+        ] {
+            let data = Data {
+                name: format!("{} Smith", name),
+                age: *age,
+            };
+            bucket.save(name.as_bytes(), &data).unwrap();
+        }
+
+        let upgrade = |key: Vec<u8>, old: Data| -> Result<_> {
+            let mut names = old.name.splitn(2, ' ');
+            let person = Person {
+                first: names.next().unwrap_or_default().to_string(),
+                last: names.next().unwrap_or_default().to_string(),
+                age: old.age,
+            };
+            Ok(Some((key, person)))
+        };
+        // each batch could run in a separate transaction
+        let cursor = bucket.migrate_to(&[b"people"], None, 2, upgrade).unwrap();
+        assert_eq!(cursor, Some(b"bert".to_vec()));
+        let cursor = bucket
+            .migrate_to(&[b"people"], cursor.as_ref().map(|c| &c[..]), 2, upgrade)
+            .unwrap();
+        assert_eq!(cursor, Some(b"dora".to_vec()));
+        let cursor = bucket
+            .migrate_to(&[b"people"], cursor.as_ref().map(|c| &c[..]), 2, upgrade)
+            .unwrap();
+        assert_eq!(cursor, None);
+
+        let people = bucket_read::<_, Person>(b"people", &store);
+        let all: Result<Vec<_>> = people.range(None, None, Order::Ascending).collect();
+        let all = all.unwrap();
+        assert_eq!(all.len(), 5);
+        assert_eq!(
+            all[4],
+            (
+                b"emil".to_vec(),
+                Person {
+                    first: "emil".to_string(),
+                    last: "Smith".to_string(),
+                    age: 34
+                }
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn migrate_to_other_namespace() {
+        let mut store = MockStorage::new();
+        let mut bucket = bucket::<_, Data>(b"data", &mut store);
+        for (name, age) in &[("john", 25), ("maria", 42), ("paul", 17)] {
+            let data = Data {
+                name: name.to_string(),
+                age: *age,
+            };
+            bucket.save(name.as_bytes(), &data).unwrap();
+        }
+
+        // move adults to a bucket keyed by age, drop the rest
+        let cursor = bucket
+            .migrate_to(&[b"by_age"], None, 10, |_, data| -> Result<_> {
+                if data.age < 18 {
+                    return Ok(None);
+                }
+                Ok(Some((data.age.to_be_bytes().to_vec(), data.name)))
+            })
+            .unwrap();
+        assert_eq!(cursor, None);
+
+        let old = bucket_read::<_, Data>(b"data", &store);
+        assert_eq!(old.range(None, None, Order::Ascending).count(), 0);
+        let by_age = bucket_read::<_, String>(b"by_age", &store);
+        let names: Result<Vec<_>> = by_age
+            .range(None, None, Order::Descending)
+            .map(|item| item.map(|(_, name)| name))
+            .collect();
+        assert_eq!(names.unwrap(), vec!["maria", "john"]);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn migrate_stops_on_error() {
+        let mut store = MockStorage::new();
+        let mut bucket = bucket::<_, Data>(b"data", &mut store);
+        for (name, age) in &[("john", 25), ("maria", 42)] {
+            let data = Data {
+                name: name.to_string(),
+                age: *age,
+            };
+            bucket.save(name.as_bytes(), &data).unwrap();
+        }
+
+        let res = bucket.migrate_to(&[b"other"], None, 10, |key, data| {
+            if data.age > 40 {
+                return Err(MyError::TooOld(data.age));
+            }
+            Ok(Some((key, data)))
+        });
+        assert_eq!(res, Err(MyError::TooOld(42)));
+        // entries before the failing one were moved, the rest is untouched
+        assert_eq!(bucket.may_load(b"john").unwrap(), None);
+        assert_eq!(bucket.load(b"maria").unwrap().age, 42);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn migrate_requires_limit() {
+        let mut store = MockStorage::new();
+        let mut bucket = bucket::<_, Data>(b"data", &mut store);
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        bucket.save(b"maria", &data).unwrap();
+
+        let res = bucket.migrate_to(&[b"data"], None, 0, |key, data| -> Result<_> {
+            Ok(Some((key, data)))
+        });
+        match res {
+            Err(StorageError::InvalidArgument { .. }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!(bucket.load(b"maria").unwrap(), data);
+    }
 }
@@ -0,0 +1,403 @@
+use serde::de::DeserializeOwned;
+use std::any::{type_name, Any, TypeId};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+#[cfg(feature = "iterator")]
+use cosmwasm_std::{Order, KV};
+use cosmwasm_std::{ReadonlyStorage, StdResult, Storage};
+
+use crate::codec::Codec;
+use crate::errors::{NotFound, Result};
+
+/// TypedCache is implemented by storages that can memoize parsed values (see CachedStorage).
+/// Bucket and Singleton provide load_cached and may_load_cached on top of it
+pub trait TypedCache: ReadonlyStorage {
+    /// may_load_typed returns the value stored at key parsed with the codec C. Parsed values are
+    /// cached per type, so loading the same key as another type or with another codec parses again
+    fn may_load_typed<C, T>(&self, key: &[u8]) -> Result<Option<T>>
+    where
+        C: Codec + 'static,
+        T: DeserializeOwned + Clone + 'static;
+}
+
+// TypedEntry is a parsed value along with the TypeId of its codec and type
+type TypedEntry = (TypeId, Box<dyn Any>);
+
+// Cache holds the memoized raw and parsed values of a CachedStorage.
+// Missing keys are cached as None, so repeated misses don't hit the storage either
+#[derive(Default)]
+struct Cache {
+    raw: RefCell<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    // the TypeId identifies codec and type, as the same key may be read differently
+    typed: RefCell<BTreeMap<Vec<u8>, TypedEntry>>,
+}
+
+impl Cache {
+    // get only caches successful reads, so a failing storage is asked again next time
+    fn get<S: ReadonlyStorage>(&self, storage: &S, key: &[u8]) -> StdResult<Option<Vec<u8>>> {
+        if let Some(value) = self.raw.borrow().get(key) {
+            return Ok(value.clone());
+        }
+        let value = storage.get(key)?;
+        self.raw.borrow_mut().insert(key.to_vec(), value.clone());
+        Ok(value)
+    }
+
+    fn may_load_typed<S, C, T>(&self, storage: &S, key: &[u8]) -> Result<Option<T>>
+    where
+        S: ReadonlyStorage,
+        C: Codec + 'static,
+        T: DeserializeOwned + Clone + 'static,
+    {
+        let type_id = TypeId::of::<(C, T)>();
+        if let Some((cached_type, value)) = self.typed.borrow().get(key) {
+            if *cached_type == type_id {
+                return Ok(value.downcast_ref::<T>().cloned());
+            }
+        }
+        match self.get(storage, key)? {
+            Some(raw) => {
+                let value: T = C::decode(&raw)?;
+                let cached: Box<dyn Any> = Box::new(value.clone());
+                self.typed
+                    .borrow_mut()
+                    .insert(key.to_vec(), (type_id, cached));
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // set stores the new raw value and drops any parsed value, so all readers see the write
+    fn set(&mut self, key: &[u8], value: Option<Vec<u8>>) {
+        self.typed.get_mut().remove(key);
+        self.raw.get_mut().insert(key.to_vec(), value);
+    }
+}
+
+/// CachedStorage memoizes all reads from another storage for its lifetime, including misses.
+/// Writes and deletes go straight through to the underlying storage and update the cache, so
+/// the cache is always consistent with the storage (which cannot be modified while borrowed).
+///
+/// It implements Storage itself, so Bucket, Singleton, PrefixedStorage, etc. can be built on top
+/// of it. With load_cached, Bucket and Singleton also memoize the parsed values. Any write to the
+/// same key through any wrapper on the same CachedStorage invalidates them.
+///
+/// range is passed through to the underlying storage uncached.
+pub struct CachedStorage<'a, S: Storage> {
+    storage: &'a mut S,
+    cache: Cache,
+}
+
+impl<'a, S: Storage> CachedStorage<'a, S> {
+    pub fn new(storage: &'a mut S) -> Self {
+        CachedStorage {
+            storage,
+            cache: Cache::default(),
+        }
+    }
+}
+
+impl<'a, S: Storage> ReadonlyStorage for CachedStorage<'a, S> {
+    fn get(&self, key: &[u8]) -> StdResult<Option<Vec<u8>>> {
+        self.cache.get(self.storage, key)
+    }
+
+    #[cfg(feature = "iterator")]
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> StdResult<Box<dyn Iterator<Item = StdResult<KV>> + 'b>> {
+        self.storage.range(start, end, order)
+    }
+}
+
+impl<'a, S: Storage> Storage for CachedStorage<'a, S> {
+    fn set(&mut self, key: &[u8], value: &[u8]) -> StdResult<()> {
+        self.storage.set(key, value)?;
+        self.cache.set(key, Some(value.to_vec()));
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) -> StdResult<()> {
+        self.storage.remove(key)?;
+        self.cache.set(key, None);
+        Ok(())
+    }
+}
+
+impl<'a, S: Storage> TypedCache for CachedStorage<'a, S> {
+    fn may_load_typed<C, T>(&self, key: &[u8]) -> Result<Option<T>>
+    where
+        C: Codec + 'static,
+        T: DeserializeOwned + Clone + 'static,
+    {
+        self.cache.may_load_typed::<_, C, T>(self.storage, key)
+    }
+}
+
+/// ReadonlyCachedStorage memoizes all reads from a ReadonlyStorage, e.g. in queries.
+/// See CachedStorage
+pub struct ReadonlyCachedStorage<'a, S: ReadonlyStorage> {
+    storage: &'a S,
+    cache: Cache,
+}
+
+impl<'a, S: ReadonlyStorage> ReadonlyCachedStorage<'a, S> {
+    pub fn new(storage: &'a S) -> Self {
+        ReadonlyCachedStorage {
+            storage,
+            cache: Cache::default(),
+        }
+    }
+}
+
+impl<'a, S: ReadonlyStorage> ReadonlyStorage for ReadonlyCachedStorage<'a, S> {
+    fn get(&self, key: &[u8]) -> StdResult<Option<Vec<u8>>> {
+        self.cache.get(self.storage, key)
+    }
+
+    #[cfg(feature = "iterator")]
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> StdResult<Box<dyn Iterator<Item = StdResult<KV>> + 'b>> {
+        self.storage.range(start, end, order)
+    }
+}
+
+impl<'a, S: ReadonlyStorage> TypedCache for ReadonlyCachedStorage<'a, S> {
+    fn may_load_typed<C, T>(&self, key: &[u8]) -> Result<Option<T>>
+    where
+        C: Codec + 'static,
+        T: DeserializeOwned + Clone + 'static,
+    {
+        self.cache.may_load_typed::<_, C, T>(self.storage, key)
+    }
+}
+
+/// may_load_cached loads namespace + key through the typed cache of the storage.
+/// namespace and key are only split to report which entry could not be parsed
+pub(crate) fn may_load_cached<S, C, T>(
+    storage: &S,
+    namespace: &[u8],
+    key: &[u8],
+) -> Result<Option<T>>
+where
+    S: TypedCache,
+    C: Codec + 'static,
+    T: DeserializeOwned + Clone + 'static,
+{
+    let full_key = [namespace, key].concat();
+    storage
+        .may_load_typed::<C, T>(&full_key)
+        .map_err(|e| e.at(namespace, key))
+}
+
+/// must_load_cached works like may_load_cached, but returns NotFound if no data is present
+pub(crate) fn must_load_cached<S, C, T>(storage: &S, namespace: &[u8], key: &[u8]) -> Result<T>
+where
+    S: TypedCache,
+    C: Codec + 'static,
+    T: DeserializeOwned + Clone + 'static,
+{
+    match may_load_cached::<S, C, T>(storage, namespace, key)? {
+        Some(t) => Ok(t),
+        None => NotFound {
+            kind: type_name::<T>(),
+            namespace: namespace.to_vec(),
+            key: key.to_vec(),
+        }
+        .fail(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+    use serde::{Deserialize, Serialize};
+    use std::cell::Cell;
+
+    use crate::{bucket, bucket_read, prefixed, singleton, singleton_read, StorageError};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct Config {
+        pub owner: String,
+        pub max_tokens: i32,
+    }
+
+    // CountingStorage counts the reads hitting the storage
+    #[derive(Default)]
+    struct CountingStorage {
+        storage: MockStorage,
+        reads: Cell<u32>,
+    }
+
+    impl ReadonlyStorage for CountingStorage {
+        fn get(&self, key: &[u8]) -> StdResult<Option<Vec<u8>>> {
+            self.reads.set(self.reads.get() + 1);
+            self.storage.get(key)
+        }
+
+        #[cfg(feature = "iterator")]
+        fn range<'b>(
+            &'b self,
+            start: Option<&[u8]>,
+            end: Option<&[u8]>,
+            order: Order,
+        ) -> StdResult<Box<dyn Iterator<Item = StdResult<KV>> + 'b>> {
+            self.storage.range(start, end, order)
+        }
+    }
+
+    impl Storage for CountingStorage {
+        fn set(&mut self, key: &[u8], value: &[u8]) -> StdResult<()> {
+            self.storage.set(key, value)
+        }
+
+        fn remove(&mut self, key: &[u8]) -> StdResult<()> {
+            self.storage.remove(key)
+        }
+    }
+
+    #[test]
+    fn memoizes_raw_reads() {
+        let mut base = CountingStorage::default();
+        base.set(b"foo", b"bar").unwrap();
+
+        let mut cached = CachedStorage::new(&mut base);
+        assert_eq!(cached.get(b"foo").unwrap(), Some(b"bar".to_vec()));
+        assert_eq!(cached.get(b"foo").unwrap(), Some(b"bar".to_vec()));
+        assert_eq!(cached.get(b"missing").unwrap(), None);
+        assert_eq!(cached.get(b"missing").unwrap(), None);
+
+        // writes go through and are visible without another read
+        cached.set(b"foo", b"baz").unwrap();
+        cached.set(b"missing", b"found").unwrap();
+        cached.remove(b"gone").unwrap();
+        assert_eq!(cached.get(b"foo").unwrap(), Some(b"baz".to_vec()));
+        assert_eq!(cached.get(b"missing").unwrap(), Some(b"found".to_vec()));
+        assert_eq!(cached.get(b"gone").unwrap(), None);
+
+        assert_eq!(base.reads.get(), 2);
+        assert_eq!(base.storage.get(b"foo").unwrap(), Some(b"baz".to_vec()));
+        assert_eq!(
+            base.storage.get(b"missing").unwrap(),
+            Some(b"found".to_vec())
+        );
+    }
+
+    #[test]
+    fn memoizes_parsed_values() {
+        let mut base = CountingStorage::default();
+        let config = Config {
+            owner: "admin".to_string(),
+            max_tokens: 1234,
+        };
+        singleton::<_, Config>(&mut base, b"config")
+            .save(&config)
+            .unwrap();
+        base.reads.set(0);
+
+        let mut cached = CachedStorage::new(&mut base);
+        for _ in 0..5 {
+            let loaded = singleton_read::<_, Config>(&cached, b"config")
+                .load_cached()
+                .unwrap();
+            assert_eq!(loaded, config);
+        }
+
+        // a write through another wrapper invalidates the parsed value
+        let mut writer = singleton::<_, Config>(&mut cached, b"config");
+        writer
+            .update(|mut c| -> Result<_> {
+                c.max_tokens += 1;
+                Ok(c)
+            })
+            .unwrap();
+        assert_eq!(writer.load_cached().unwrap().max_tokens, 1235);
+        writer.remove().unwrap();
+        assert_eq!(writer.may_load_cached().unwrap(), None);
+
+        assert_eq!(base.reads.get(), 1);
+    }
+
+    #[test]
+    fn bucket_stays_coherent() {
+        let mut base = MockStorage::new();
+        let mut cached = CachedStorage::new(&mut base);
+        bucket::<_, i32>(b"scores", &mut cached)
+            .save(b"alice", &10)
+            .unwrap();
+        assert_eq!(
+            bucket_read::<_, i32>(b"scores", &cached)
+                .load_cached(b"alice")
+                .unwrap(),
+            10
+        );
+
+        // raw writes to the same key through a prefix are seen as well
+        prefixed(b"scores", &mut cached)
+            .set(b"alice", b"42")
+            .unwrap();
+        let scores = bucket::<_, i32>(b"scores", &mut cached);
+        assert_eq!(scores.load_cached(b"alice").unwrap(), 42);
+
+        // the same key can be read as another type
+        let reader = bucket_read::<_, i64>(b"scores", &cached);
+        assert_eq!(reader.load_cached(b"alice").unwrap(), 42i64);
+        match reader.load_cached(b"bob").unwrap_err() {
+            StorageError::NotFound { key, .. } => assert_eq!(key, b"bob".to_vec()),
+            e => panic!("Unexpected error {}", e),
+        }
+        let texts = bucket_read::<_, String>(b"scores", &cached);
+        match texts.may_load_cached(b"alice").unwrap_err() {
+            StorageError::ParseErr { key, .. } => assert_eq!(key, b"alice".to_vec()),
+            e => panic!("Unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn readonly_cache() {
+        let mut base = CountingStorage::default();
+        bucket::<_, i32>(b"scores", &mut base)
+            .save(b"alice", &10)
+            .unwrap();
+        base.reads.set(0);
+
+        let cached = ReadonlyCachedStorage::new(&base);
+        let scores = bucket_read::<_, i32>(b"scores", &cached);
+        assert_eq!(scores.load_cached(b"alice").unwrap(), 10);
+        assert_eq!(scores.load(b"alice").unwrap(), 10);
+        assert_eq!(scores.may_load_cached(b"bob").unwrap(), None);
+        assert_eq!(scores.may_load(b"bob").unwrap(), None);
+        assert_eq!(base.reads.get(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_sees_writes() {
+        let mut base = MockStorage::new();
+        let mut cached = CachedStorage::new(&mut base);
+        cached.set(b"a", b"1").unwrap();
+        assert_eq!(cached.get(b"b").unwrap(), None);
+        cached.set(b"b", b"2").unwrap();
+        let all: StdResult<Vec<KV>> = cached
+            .range(None, None, Order::Ascending)
+            .unwrap()
+            .collect();
+        assert_eq!(
+            all.unwrap(),
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"2".to_vec())
+            ]
+        );
+    }
+}
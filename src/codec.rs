@@ -0,0 +1,141 @@
+use serde::{de::DeserializeOwned, ser::Serialize};
+
+#[cfg(feature = "bincode")]
+use std::any::type_name;
+
+use crate::errors::Result;
+#[cfg(feature = "bincode")]
+use crate::errors::{ParseErr, SerializeErr};
+use crate::type_helpers::{deserialize, serialize};
+
+/// Codec defines how values are encoded before they are written to storage.
+///
+/// Bucket, Singleton and TypedStorage are generic over the codec and default to Json.
+/// Note that all readers and writers of one key must agree on the codec.
+pub trait Codec {
+    /// NEVER_STARTS_WITH_ZERO is true if encoded values never start with a 0 byte. Versioned
+    /// values rely on this to tell apart values that were stored without version
+    const NEVER_STARTS_WITH_ZERO: bool = false;
+
+    fn encode<T: Serialize>(data: &T) -> Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(value: &[u8]) -> Result<T>;
+}
+
+/// Json is the default codec, producing the same bytes as the crate level serialize function
+pub struct Json;
+
+impl Codec for Json {
+    // json text starts with whitespace or the first character of a value
+    const NEVER_STARTS_WITH_ZERO: bool = true;
+
+    fn encode<T: Serialize>(data: &T) -> Result<Vec<u8>> {
+        serialize(data)
+    }
+
+    fn decode<T: DeserializeOwned>(value: &[u8]) -> Result<T> {
+        deserialize(value)
+    }
+}
+
+/// Bincode stores values in the bincode format with fixed size integers.
+/// This is much smaller and faster to parse than json, but not self-describing.
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode<T: Serialize>(data: &T) -> Result<Vec<u8>> {
+        bincode::serialize(data).or_else(encode_err::<T, _>)
+    }
+
+    fn decode<T: DeserializeOwned>(value: &[u8]) -> Result<T> {
+        bincode::deserialize(value).or_else(decode_err::<T>)
+    }
+}
+
+/// CompactBincode stores values in the bincode format, using variable length encoding
+/// for all integers. This is the most compact codec for data with many small numbers.
+#[cfg(feature = "bincode")]
+pub struct CompactBincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for CompactBincode {
+    fn encode<T: Serialize>(data: &T) -> Result<Vec<u8>> {
+        use bincode::Options;
+        bincode::DefaultOptions::new()
+            .serialize(data)
+            .or_else(encode_err::<T, _>)
+    }
+
+    fn decode<T: DeserializeOwned>(value: &[u8]) -> Result<T> {
+        use bincode::Options;
+        bincode::DefaultOptions::new()
+            .deserialize(value)
+            .or_else(decode_err::<T>)
+    }
+}
+
+#[cfg(feature = "bincode")]
+fn encode_err<T, U>(err: bincode::Error) -> Result<U> {
+    SerializeErr {
+        kind: type_name::<T>(),
+        msg: err.to_string(),
+    }
+    .fail()
+}
+
+#[cfg(feature = "bincode")]
+fn decode_err<T>(err: bincode::Error) -> Result<T> {
+    ParseErr {
+        kind: type_name::<T>(),
+        namespace: vec![],
+        key: vec![],
+        msg: err.to_string(),
+    }
+    .fail()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Data {
+        pub name: String,
+        pub age: i32,
+    }
+
+    #[test]
+    fn json_matches_serialize() {
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        let encoded = Json::encode(&data).unwrap();
+        assert_eq!(encoded, serialize(&data).unwrap());
+        assert_eq!(encoded, br#"{"name":"Maria","age":42}"#.to_vec());
+        assert_eq!(Json::decode::<Data>(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn bincode_round_trip() {
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+
+        let fixed = Bincode::encode(&data).unwrap();
+        assert_eq!(Bincode::decode::<Data>(&fixed).unwrap(), data);
+        let compact = CompactBincode::encode(&data).unwrap();
+        assert_eq!(CompactBincode::decode::<Data>(&compact).unwrap(), data);
+
+        // 8 byte length + 5 bytes + 4 byte integer vs. 1 byte length + 5 bytes + 1 byte integer
+        assert_eq!(fixed.len(), 17);
+        assert_eq!(compact.len(), 7);
+
+        assert!(Bincode::decode::<Data>(&fixed[..10]).is_err());
+        assert!(CompactBincode::decode::<Data>(b"").is_err());
+    }
+}
@@ -0,0 +1,447 @@
+use serde::{de::DeserializeOwned, ser::Serialize};
+use std::marker::PhantomData;
+
+use cosmwasm_std::{ReadonlyStorage, Storage};
+
+use crate::bucket::{Bucket, ReadonlyBucket};
+use crate::errors::{Overflow, Result};
+use crate::namespace_helpers::try_key_prefix;
+use crate::prefix::{PrefixedStorage, ReadonlyPrefixedStorage};
+use crate::singleton::{ReadonlySingleton, Singleton};
+
+const HEAD_KEY: &[u8] = b"head";
+const TAIL_KEY: &[u8] = b"tail";
+const VALUES_NAMESPACE: &[u8] = b"values";
+
+pub fn deque<'a, S: Storage, T>(namespace: &[u8], storage: &'a mut S) -> Deque<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    Deque::new(namespace, storage)
+}
+
+pub fn deque_read<'a, S: ReadonlyStorage, T>(
+    namespace: &[u8],
+    storage: &'a S,
+) -> ReadonlyDeque<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    ReadonlyDeque::new(namespace, storage)
+}
+
+/// Deque is a double-ended queue stored under one namespace.
+///
+/// The head (position of the first element) and tail (position after the last element) are kept
+/// in two singletons, the elements in a bucket keyed by their big-endian u32 position.
+/// All operations only touch a constant number of keys. Positions wrap around at u32::MAX,
+/// so push_front and pop_back work from the start, and the deque holds up to u32::MAX elements.
+pub struct Deque<'a, S: Storage, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    storage: &'a mut S,
+    namespace: Vec<u8>,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+}
+
+impl<'a, S: Storage, T> Deque<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(namespace: &[u8], storage: &'a mut S) -> Self {
+        Deque {
+            storage,
+            namespace: namespace.to_vec(),
+            data: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a mut S) -> Result<Self> {
+        try_key_prefix(namespace)?;
+        Ok(Self::new(namespace, storage))
+    }
+
+    /// push_back adds an element after the last one
+    pub fn push_back(&mut self, value: &T) -> Result<()> {
+        let (head, tail) = self.bounds()?;
+        check_space(head, tail)?;
+        self.values().save(&tail.to_be_bytes(), value)?;
+        self.save_counter(TAIL_KEY, tail.wrapping_add(1))
+    }
+
+    /// push_front adds an element before the first one
+    pub fn push_front(&mut self, value: &T) -> Result<()> {
+        let (head, tail) = self.bounds()?;
+        check_space(head, tail)?;
+        let head = head.wrapping_sub(1);
+        self.values().save(&head.to_be_bytes(), value)?;
+        self.save_counter(HEAD_KEY, head)
+    }
+
+    /// pop_back removes and returns the last element, or None if the deque is empty
+    pub fn pop_back(&mut self) -> Result<Option<T>> {
+        let (head, tail) = self.bounds()?;
+        if head == tail {
+            return Ok(None);
+        }
+        let tail = tail.wrapping_sub(1);
+        let value = self.take(tail)?;
+        self.save_counter(TAIL_KEY, tail)?;
+        Ok(Some(value))
+    }
+
+    /// pop_front removes and returns the first element, or None if the deque is empty
+    pub fn pop_front(&mut self) -> Result<Option<T>> {
+        let (head, tail) = self.bounds()?;
+        if head == tail {
+            return Ok(None);
+        }
+        let value = self.take(head)?;
+        self.save_counter(HEAD_KEY, head.wrapping_add(1))?;
+        Ok(Some(value))
+    }
+
+    /// len returns the number of elements in the deque
+    pub fn len(&self) -> Result<u32> {
+        deque_len(self.storage, &self.namespace)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// front returns the first element without removing it, or None if the deque is empty
+    pub fn front(&self) -> Result<Option<T>> {
+        deque_get(self.storage, &self.namespace, 0)
+    }
+
+    /// back returns the last element without removing it, or None if the deque is empty
+    pub fn back(&self) -> Result<Option<T>> {
+        deque_back(self.storage, &self.namespace)
+    }
+
+    /// get returns the element at the given index counted from the front,
+    /// or None if the index is out of bounds
+    pub fn get(&self, index: u32) -> Result<Option<T>> {
+        deque_get(self.storage, &self.namespace, index)
+    }
+
+    /// iter returns all elements from front to back. Elements are only loaded
+    /// when the iterator reaches them
+    pub fn iter<'b>(&'b self) -> Result<Box<dyn Iterator<Item = Result<T>> + 'b>> {
+        deque_iter(self.storage, &self.namespace)
+    }
+
+    fn bounds(&self) -> Result<(u32, u32)> {
+        bounds(self.storage, &self.namespace)
+    }
+
+    fn values(&mut self) -> Bucket<'_, S, T> {
+        Bucket::multilevel(&[&self.namespace, VALUES_NAMESPACE], self.storage)
+    }
+
+    // take loads the element at the given position and removes it from storage
+    fn take(&mut self, pos: u32) -> Result<T> {
+        let mut values = self.values();
+        let value = values.load(&pos.to_be_bytes())?;
+        values.remove(&pos.to_be_bytes())?;
+        Ok(value)
+    }
+
+    fn save_counter(&mut self, name: &[u8], value: u32) -> Result<()> {
+        let mut store = PrefixedStorage::new(&self.namespace, self.storage);
+        Singleton::<_, u32>::new(&mut store, name).save(&value)
+    }
+}
+
+/// ReadonlyDeque only requires a ReadonlyStorage and exposes only the
+/// methods of Deque that don't modify state.
+pub struct ReadonlyDeque<'a, S: ReadonlyStorage, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    storage: &'a S,
+    namespace: Vec<u8>,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+}
+
+impl<'a, S: ReadonlyStorage, T> ReadonlyDeque<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(namespace: &[u8], storage: &'a S) -> Self {
+        ReadonlyDeque {
+            storage,
+            namespace: namespace.to_vec(),
+            data: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a S) -> Result<Self> {
+        try_key_prefix(namespace)?;
+        Ok(Self::new(namespace, storage))
+    }
+
+    /// len returns the number of elements in the deque
+    pub fn len(&self) -> Result<u32> {
+        deque_len(self.storage, &self.namespace)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// front returns the first element, or None if the deque is empty
+    pub fn front(&self) -> Result<Option<T>> {
+        deque_get(self.storage, &self.namespace, 0)
+    }
+
+    /// back returns the last element, or None if the deque is empty
+    pub fn back(&self) -> Result<Option<T>> {
+        deque_back(self.storage, &self.namespace)
+    }
+
+    /// get returns the element at the given index counted from the front,
+    /// or None if the index is out of bounds
+    pub fn get(&self, index: u32) -> Result<Option<T>> {
+        deque_get(self.storage, &self.namespace, index)
+    }
+
+    /// iter returns all elements from front to back, see Deque::iter
+    pub fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<T>> + 'a>> {
+        deque_iter(self.storage, &self.namespace)
+    }
+}
+
+// check_space returns an error if another element would make head and tail overlap
+fn check_space(head: u32, tail: u32) -> Result<()> {
+    if tail.wrapping_sub(head) == std::u32::MAX {
+        return Overflow {
+            msg: "deque is full",
+        }
+        .fail();
+    }
+    Ok(())
+}
+
+fn bounds<S: ReadonlyStorage>(storage: &S, namespace: &[u8]) -> Result<(u32, u32)> {
+    let store = ReadonlyPrefixedStorage::new(namespace, storage);
+    let head = ReadonlySingleton::<_, u32>::new(&store, HEAD_KEY).may_load()?;
+    let tail = ReadonlySingleton::<_, u32>::new(&store, TAIL_KEY).may_load()?;
+    Ok((head.unwrap_or_default(), tail.unwrap_or_default()))
+}
+
+fn deque_len<S: ReadonlyStorage>(storage: &S, namespace: &[u8]) -> Result<u32> {
+    let (head, tail) = bounds(storage, namespace)?;
+    Ok(tail.wrapping_sub(head))
+}
+
+fn deque_get<S: ReadonlyStorage, T>(storage: &S, namespace: &[u8], index: u32) -> Result<Option<T>>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let (head, tail) = bounds(storage, namespace)?;
+    if index >= tail.wrapping_sub(head) {
+        return Ok(None);
+    }
+    let pos = head.wrapping_add(index);
+    let values = ReadonlyBucket::<_, T>::multilevel(&[namespace, VALUES_NAMESPACE], storage);
+    values.load(&pos.to_be_bytes()).map(Some)
+}
+
+fn deque_back<S: ReadonlyStorage, T>(storage: &S, namespace: &[u8]) -> Result<Option<T>>
+where
+    T: Serialize + DeserializeOwned,
+{
+    match deque_len(storage, namespace)? {
+        0 => Ok(None),
+        len => deque_get(storage, namespace, len - 1),
+    }
+}
+
+fn deque_iter<'a, S: ReadonlyStorage, T>(
+    storage: &'a S,
+    namespace: &[u8],
+) -> Result<Box<dyn Iterator<Item = Result<T>> + 'a>>
+where
+    T: Serialize + DeserializeOwned + 'a,
+{
+    let (head, tail) = bounds(storage, namespace)?;
+    let values = ReadonlyBucket::<_, T>::multilevel(&[namespace, VALUES_NAMESPACE], storage);
+    let mapped =
+        (0..tail.wrapping_sub(head)).map(move |i| values.load(&head.wrapping_add(i).to_be_bytes()));
+    Ok(Box::new(mapped))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::StorageError;
+    use cosmwasm_std::testing::MockStorage;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct Job {
+        pub id: u32,
+        pub name: String,
+    }
+
+    fn job(id: u32) -> Job {
+        Job {
+            id,
+            name: format!("job {}", id),
+        }
+    }
+
+    #[test]
+    fn fifo_queue() {
+        let mut store = MockStorage::new();
+        let mut queue = deque::<_, Job>(b"jobs", &mut store);
+
+        assert!(queue.is_empty().unwrap());
+        assert_eq!(queue.pop_front().unwrap(), None);
+        assert_eq!(queue.front().unwrap(), None);
+        assert_eq!(queue.back().unwrap(), None);
+
+        queue.push_back(&job(1)).unwrap();
+        queue.push_back(&job(2)).unwrap();
+        queue.push_back(&job(3)).unwrap();
+        assert_eq!(queue.len().unwrap(), 3);
+        assert_eq!(queue.front().unwrap(), Some(job(1)));
+        assert_eq!(queue.back().unwrap(), Some(job(3)));
+
+        assert_eq!(queue.pop_front().unwrap(), Some(job(1)));
+        assert_eq!(queue.pop_front().unwrap(), Some(job(2)));
+        queue.push_back(&job(4)).unwrap();
+        assert_eq!(queue.pop_front().unwrap(), Some(job(3)));
+        assert_eq!(queue.pop_front().unwrap(), Some(job(4)));
+        assert_eq!(queue.pop_front().unwrap(), None);
+        assert!(queue.is_empty().unwrap());
+    }
+
+    #[test]
+    fn both_ends() {
+        let mut store = MockStorage::new();
+        let mut queue = deque::<_, Job>(b"jobs", &mut store);
+
+        // starting at the front wraps around below 0
+        queue.push_front(&job(2)).unwrap();
+        queue.push_front(&job(1)).unwrap();
+        queue.push_back(&job(3)).unwrap();
+        assert_eq!(queue.len().unwrap(), 3);
+
+        assert_eq!(queue.get(0).unwrap(), Some(job(1)));
+        assert_eq!(queue.get(1).unwrap(), Some(job(2)));
+        assert_eq!(queue.get(2).unwrap(), Some(job(3)));
+        assert_eq!(queue.get(3).unwrap(), None);
+
+        let all: Result<Vec<Job>> = queue.iter().unwrap().collect();
+        assert_eq!(all.unwrap(), vec![job(1), job(2), job(3)]);
+
+        assert_eq!(queue.pop_back().unwrap(), Some(job(3)));
+        assert_eq!(queue.pop_back().unwrap(), Some(job(2)));
+        assert_eq!(queue.pop_back().unwrap(), Some(job(1)));
+        assert_eq!(queue.pop_back().unwrap(), None);
+        assert_eq!(queue.len().unwrap(), 0);
+    }
+
+    #[test]
+    fn popped_values_are_removed() {
+        let mut store = MockStorage::new();
+        let mut queue = deque::<_, Job>(b"jobs", &mut store);
+        queue.push_back(&job(1)).unwrap();
+        queue.pop_front().unwrap();
+
+        let values = ReadonlyBucket::<_, Job>::multilevel(&[b"jobs", VALUES_NAMESPACE], &store);
+        assert_eq!(values.may_load(&0u32.to_be_bytes()).unwrap(), None);
+    }
+
+    #[test]
+    fn wraps_around_max() {
+        let mut store = MockStorage::new();
+        let mut queue = deque::<_, Job>(b"jobs", &mut store);
+
+        // start right before the end of the u32 range
+        queue.save_counter(HEAD_KEY, std::u32::MAX - 1).unwrap();
+        queue.save_counter(TAIL_KEY, std::u32::MAX - 1).unwrap();
+        for id in 0..4 {
+            queue.push_back(&job(id)).unwrap();
+        }
+        assert_eq!(queue.len().unwrap(), 4);
+        assert_eq!(queue.bounds().unwrap(), (std::u32::MAX - 1, 2));
+        assert_eq!(queue.back().unwrap(), Some(job(3)));
+
+        let all: Result<Vec<Job>> = queue.iter().unwrap().collect();
+        assert_eq!(all.unwrap(), vec![job(0), job(1), job(2), job(3)]);
+        for id in 0..4 {
+            assert_eq!(queue.pop_front().unwrap(), Some(job(id)));
+        }
+        assert!(queue.is_empty().unwrap());
+    }
+
+    #[test]
+    fn full_deque_errors() {
+        let mut store = MockStorage::new();
+        let mut queue = deque::<_, Job>(b"jobs", &mut store);
+
+        // pretend we hold u32::MAX - 2 elements
+        queue.save_counter(HEAD_KEY, 5).unwrap();
+        queue.save_counter(TAIL_KEY, 2).unwrap();
+        assert_eq!(queue.len().unwrap(), std::u32::MAX - 2);
+        queue.push_back(&job(1)).unwrap();
+        queue.push_front(&job(2)).unwrap();
+        assert_eq!(queue.len().unwrap(), std::u32::MAX);
+
+        match queue.push_back(&job(3)) {
+            Err(StorageError::Overflow { .. }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert!(queue.push_front(&job(3)).is_err());
+        assert_eq!(queue.len().unwrap(), std::u32::MAX);
+        assert_eq!(queue.back().unwrap(), Some(job(1)));
+        assert_eq!(queue.front().unwrap(), Some(job(2)));
+    }
+
+    #[test]
+    fn readonly_and_isolated() {
+        let mut store = MockStorage::new();
+        deque::<_, Job>(b"jobs", &mut store)
+            .push_back(&job(1))
+            .unwrap();
+        deque::<_, Job>(b"other", &mut store)
+            .push_back(&job(2))
+            .unwrap();
+
+        let reader = deque_read::<_, Job>(b"jobs", &store);
+        assert_eq!(reader.len().unwrap(), 1);
+        assert_eq!(reader.front().unwrap(), Some(job(1)));
+        assert_eq!(reader.back().unwrap(), Some(job(1)));
+        assert_eq!(reader.get(0).unwrap(), Some(job(1)));
+        let all: Result<Vec<Job>> = reader.iter().unwrap().collect();
+        assert_eq!(all.unwrap(), vec![job(1)]);
+
+        let other = deque_read::<_, Job>(b"other", &store);
+        assert_eq!(other.front().unwrap(), Some(job(2)));
+    }
+
+    #[test]
+    fn try_new_rejects_long_namespace() {
+        let mut store = MockStorage::new();
+        let long_namespace = vec![b'a'; 0x10000];
+        match Deque::<_, Job>::try_new(&long_namespace, &mut store) {
+            Err(StorageError::NamespaceTooLong { length }) => assert_eq!(length, 0x10000),
+            _ => panic!("expected NamespaceTooLong"),
+        }
+        assert!(ReadonlyDeque::<_, Job>::try_new(&long_namespace, &store).is_err());
+
+        let mut queue = Deque::<_, Job>::try_new(b"jobs", &mut store).unwrap();
+        queue.push_back(&job(1)).unwrap();
+        let reader = ReadonlyDeque::<_, Job>::try_new(b"jobs", &store).unwrap();
+        assert_eq!(reader.front().unwrap(), Some(job(1)));
+    }
+}
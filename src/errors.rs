@@ -0,0 +1,254 @@
+use snafu::Snafu;
+
+use cosmwasm_std::{generic_err, not_found, StdError};
+
+use crate::namespace_helpers::{decode_key, printable_key};
+
+/// StorageError is returned by all storage helpers in this crate.
+///
+/// It converts into cosmwasm_std::StdError, so `?` works in functions returning a StdResult.
+/// Contracts with their own error type only need to implement `From<StorageError>`.
+///
+/// NotFound and ParseErr carry the raw namespace (length-prefixed as in storage, empty if none)
+/// and key of the entry. They are printed in a readable form when displaying the error,
+/// and kept in the message when converting into a StdError.
+#[derive(Debug, PartialEq, Snafu)]
+#[snafu(visibility = "pub")]
+pub enum StorageError {
+    #[snafu(display("{} not found{}", kind, location(namespace, key)))]
+    NotFound {
+        kind: &'static str,
+        namespace: Vec<u8>,
+        key: Vec<u8>,
+    },
+    #[snafu(display("Error parsing {}{}: {}", kind, location(namespace, key), msg))]
+    ParseErr {
+        kind: &'static str,
+        namespace: Vec<u8>,
+        key: Vec<u8>,
+        msg: String,
+    },
+    #[snafu(display("Error serializing {}: {}", kind, msg))]
+    SerializeErr { kind: &'static str, msg: String },
+    #[snafu(display("Invalid key: {}", msg))]
+    InvalidKey { msg: &'static str },
+    #[snafu(display("Invalid argument: {}", msg))]
+    InvalidArgument { msg: &'static str },
+    #[snafu(display(
+        "Namespace of {} bytes too long: only supports namespaces up to length 0xFFFF",
+        length
+    ))]
+    NamespaceTooLong { length: usize },
+    /// IndexConflict is returned by IndexedBucket::save if the value of a unique index
+    /// is already claimed by another primary key
+    #[snafu(display(
+        "Unique index conflict on {}: {} already claimed",
+        index,
+        printable_key(value)
+    ))]
+    IndexConflict { index: String, value: Vec<u8> },
+    #[snafu(display("Invalid index {}: {}", index, msg))]
+    InvalidIndex { index: String, msg: &'static str },
+    #[snafu(display("Overflow: {}", msg))]
+    Overflow { msg: &'static str },
+    #[snafu(display("No snapshot at height {}", height))]
+    NotCheckpointed { height: u64 },
+    #[snafu(display("Unsupported version {} of {}", version, kind))]
+    UnsupportedVersion { kind: &'static str, version: u16 },
+    /// Std is returned if the underlying storage fails
+    #[snafu(display("Storage error: {}", source))]
+    Std { source: StdError },
+}
+
+pub type Result<T, E = StorageError> = core::result::Result<T, E>;
+
+impl StorageError {
+    // at fills in the location of NotFound and ParseErr errors, others are returned as is
+    pub(crate) fn at(self, at_namespace: &[u8], at_key: &[u8]) -> Self {
+        match self {
+            StorageError::NotFound { kind, .. } => StorageError::NotFound {
+                kind,
+                namespace: at_namespace.to_vec(),
+                key: at_key.to_vec(),
+            },
+            StorageError::ParseErr { kind, msg, .. } => StorageError::ParseErr {
+                kind,
+                namespace: at_namespace.to_vec(),
+                key: at_key.to_vec(),
+                msg,
+            },
+            err => err,
+        }
+    }
+}
+
+// location describes where an entry is stored, e.g. ` in people/2020 at maria`.
+// Returns an empty string if both namespace and key are empty
+fn location(namespace: &[u8], key: &[u8]) -> String {
+    let mut out = String::new();
+    if !namespace.is_empty() {
+        out.push_str(" in ");
+        out.push_str(&printable_namespace(namespace));
+    }
+    if !key.is_empty() {
+        out.push_str(" at ");
+        out.push_str(&printable_key(key));
+    }
+    out
+}
+
+// printable_namespace splits a (possibly nested) namespace into its components, separated by "/".
+// Falls back to hex if it is not a valid sequence of length-prefixed namespaces
+fn printable_namespace(namespace: &[u8]) -> String {
+    let decoded = decode_key(namespace);
+    if decoded.key.is_empty() {
+        decoded.to_string()
+    } else {
+        printable_key(namespace)
+    }
+}
+
+impl From<StdError> for StorageError {
+    fn from(source: StdError) -> Self {
+        StorageError::Std { source }
+    }
+}
+
+// NotFound is kept as cosmwasm NotFound, so contracts (and their clients) can still tell missing
+// data apart. As the cosmwasm error only holds a kind, the location is appended to it, e.g.
+// `Data in people at maria not found`
+impl From<StorageError> for StdError {
+    fn from(err: StorageError) -> Self {
+        match err {
+            StorageError::NotFound {
+                kind,
+                namespace,
+                key,
+            } => not_found(format!("{}{}", kind, location(&namespace, &key))),
+            StorageError::Std { source } => source,
+            // everything else is reported with the same message as a generic error
+            err => generic_err(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::namespace_helpers::{key_prefix, key_prefix_nested};
+    use cosmwasm_std::StdResult;
+
+    #[test]
+    fn convert_to_cosmwasm_error() {
+        let err: StdError = StorageError::NotFound {
+            kind: "Data",
+            namespace: key_prefix(b"people"),
+            key: b"foo".to_vec(),
+        }
+        .into();
+        match err {
+            // the location is kept
+            StdError::NotFound { ref kind, .. } => assert_eq!(kind, "Data in people at foo"),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+        assert_eq!(err.to_string(), "Data in people at foo not found");
+
+        // without a location, only the type is left
+        let err: StdError = StorageError::NotFound {
+            kind: "Data",
+            namespace: vec![],
+            key: vec![],
+        }
+        .into();
+        match err {
+            StdError::NotFound { kind, .. } => assert_eq!(kind, "Data"),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        let err: StdError = StorageError::IndexConflict {
+            index: "username".to_string(),
+            value: b"alice".to_vec(),
+        }
+        .into();
+        match err {
+            StdError::GenericErr { msg, .. } => {
+                assert_eq!(
+                    msg,
+                    "Unique index conflict on username: alice already claimed"
+                )
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn question_mark_converts() {
+        fn load() -> Result<()> {
+            InvalidKey { msg: "too short" }.fail()
+        }
+        fn handle() -> StdResult<()> {
+            load()?;
+            Ok(())
+        }
+        match handle() {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg, "Invalid key: too short"),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn display_location() {
+        let err = StorageError::NotFound {
+            kind: "Data",
+            namespace: key_prefix_nested(&[b"people", b"2020"]),
+            key: b"maria".to_vec(),
+        };
+        assert_eq!(err.to_string(), "Data not found in people/2020 at maria");
+
+        // binary keys are printed as hex
+        let err = StorageError::ParseErr {
+            kind: "Data",
+            namespace: key_prefix(b"counts"),
+            key: 1234u32.to_be_bytes().to_vec(),
+            msg: "EOF".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Error parsing Data in counts at 0x000004d2: EOF"
+        );
+
+        // no location at all
+        let err = StorageError::NotFound {
+            kind: "Data",
+            namespace: vec![],
+            key: vec![],
+        };
+        assert_eq!(err.to_string(), "Data not found");
+    }
+
+    #[test]
+    fn printable_parts() {
+        assert_eq!(printable_key(b"hello"), "hello");
+        assert_eq!(printable_key(b"\x00\x01"), "0x0001");
+        assert_eq!(printable_key(b"\xff\xfe"), "0xfffe");
+        assert_eq!(printable_key(b"line\n"), "0x6c696e650a");
+
+        assert_eq!(printable_namespace(&key_prefix(b"config")), "config");
+        // an invalid length prefix is shown as raw hex
+        assert_eq!(printable_namespace(b"\x00\x05ab"), "0x00056162");
+    }
+
+    #[test]
+    fn at_sets_location() {
+        let err = StorageError::NotFound {
+            kind: "Data",
+            namespace: vec![],
+            key: vec![],
+        }
+        .at(b"\x00\x01a", b"b");
+        assert_eq!(err.to_string(), "Data not found in a at b");
+
+        let err = StorageError::InvalidKey { msg: "oops" }.at(b"\x00\x01a", b"b");
+        assert_eq!(err, StorageError::InvalidKey { msg: "oops" });
+    }
+}
@@ -0,0 +1,479 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::any::type_name;
+use std::marker::PhantomData;
+
+#[cfg(feature = "iterator")]
+use cosmwasm_std::{Order, StdResult};
+use cosmwasm_std::{ReadonlyStorage, Storage};
+
+use crate::codec::{Codec, Json};
+use crate::errors::{NotFound, Result};
+#[cfg(feature = "iterator")]
+use crate::namespace_helpers::range_with_prefix;
+use crate::namespace_helpers::{
+    get_with_prefix, key_prefix_nested, remove_with_prefix, set_with_prefix, try_key_prefix,
+};
+#[cfg(feature = "iterator")]
+use crate::type_helpers::deserialize_kv;
+use crate::type_helpers::may_deserialize;
+
+// the values and both expiry indexes live in their own sub-namespace of the bucket namespace.
+// Index keys are the expiry (u64 big endian) followed by the primary key, so they are sorted
+// by expiry, and the index values are the primary keys
+const DATA_NAMESPACE: &[u8] = b"data";
+const BY_HEIGHT_NAMESPACE: &[u8] = b"by_height";
+const BY_TIME_NAMESPACE: &[u8] = b"by_time";
+
+pub fn expiring_bucket<'a, S: Storage, T>(
+    namespace: &[u8],
+    storage: &'a mut S,
+) -> ExpiringBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    ExpiringBucket::new(namespace, storage)
+}
+
+pub fn expiring_bucket_read<'a, S: ReadonlyStorage, T>(
+    namespace: &[u8],
+    storage: &'a S,
+) -> ReadonlyExpiringBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    ReadonlyExpiringBucket::new(namespace, storage)
+}
+
+/// Expiration defines when an entry of an ExpiringBucket lapses
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Expiration {
+    /// Expires once the block height is reached
+    AtHeight(u64),
+    /// Expires once the block time (in seconds) is reached
+    AtTime(u64),
+    /// Never expires
+    Never,
+}
+
+impl Expiration {
+    pub fn is_expired(&self, now: &BlockTime) -> bool {
+        match *self {
+            Expiration::AtHeight(height) => now.height >= height,
+            Expiration::AtTime(time) => now.time >= time,
+            Expiration::Never => false,
+        }
+    }
+
+    // index_entry returns the namespace of the expiry index and the key of the entry in it,
+    // None if it never expires
+    fn index_entry(&self, key: &[u8]) -> Option<(&'static [u8], Vec<u8>)> {
+        let (namespace, expiry) = match *self {
+            Expiration::AtHeight(height) => (BY_HEIGHT_NAMESPACE, height),
+            Expiration::AtTime(time) => (BY_TIME_NAMESPACE, time),
+            Expiration::Never => return None,
+        };
+        Some((namespace, [&expiry.to_be_bytes()[..], key].concat()))
+    }
+}
+
+/// BlockTime is the current block height and time (in seconds), used to decide which entries
+/// are expired. Contracts can build it from the block info of their environment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockTime {
+    pub height: u64,
+    pub time: u64,
+}
+
+// Entry is the stored value together with its expiration
+#[derive(Serialize, Deserialize)]
+struct Entry<T> {
+    value: T,
+    expires: Expiration,
+}
+
+/// ExpiringBucket stores values that lapse at a block height or time, e.g. temporary approvals.
+///
+/// Reads take the current BlockTime and hide expired entries, so they behave as if they were
+/// removed. Expired entries still use storage until they are deleted with `prune_expired`,
+/// which walks an index sorted by expiry and only touches stale entries.
+pub struct ExpiringBucket<'a, S: Storage, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    storage: &'a mut S,
+    namespace: Vec<u8>,
+    data_prefix: Vec<u8>,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+}
+
+impl<'a, S: Storage, T> ExpiringBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(namespace: &[u8], storage: &'a mut S) -> Self {
+        ExpiringBucket {
+            storage,
+            namespace: namespace.to_vec(),
+            data_prefix: key_prefix_nested(&[namespace, DATA_NAMESPACE]),
+            data: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a mut S) -> Result<Self> {
+        try_key_prefix(namespace)?;
+        Ok(Self::new(namespace, storage))
+    }
+
+    /// save stores the value until it expires, replacing any previous value and expiration
+    pub fn save(&mut self, key: &[u8], data: &T, expires: Expiration) -> Result<()> {
+        // encode first, so nothing is written on serialization errors
+        let entry = Json::encode(&Entry {
+            value: data,
+            expires,
+        })?;
+        self.remove_from_index(key)?;
+        if let Some((index, index_key)) = expires.index_entry(key) {
+            let prefix = key_prefix_nested(&[&self.namespace, index]);
+            set_with_prefix(self.storage, &prefix, &index_key, key)?;
+        }
+        set_with_prefix(self.storage, &self.data_prefix, key, &entry)?;
+        Ok(())
+    }
+
+    /// remove deletes the value, whether it is expired or not
+    pub fn remove(&mut self, key: &[u8]) -> Result<()> {
+        self.remove_from_index(key)?;
+        remove_with_prefix(self.storage, &self.data_prefix, key)?;
+        Ok(())
+    }
+
+    /// load returns NotFound if there is no value at the key or it is expired
+    pub fn load(&self, key: &[u8], now: &BlockTime) -> Result<T> {
+        load::<_, T>(self.storage, &self.data_prefix, key, now)
+    }
+
+    /// may_load returns Ok(None) if there is no value at the key or it is expired
+    pub fn may_load(&self, key: &[u8], now: &BlockTime) -> Result<Option<T>> {
+        may_load::<_, T>(self.storage, &self.data_prefix, key, now)
+    }
+
+    /// range iterates over all entries that are not expired with start <= key < end
+    /// (unbounded if None) in the given order. See Bucket::range
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        now: &BlockTime,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'b> {
+        range(self.storage, &self.data_prefix, *now, start, end, order)
+    }
+
+    /// prune_expired deletes up to limit expired entries, those that expired first are deleted
+    /// first. Returns the number of deleted entries, so the contract can call it again if it
+    /// equals limit. Entries expiring by height are pruned before those expiring by time
+    #[cfg(feature = "iterator")]
+    pub fn prune_expired(&mut self, now: &BlockTime, limit: u32) -> Result<u32> {
+        let mut stale = Vec::new();
+        for (index, current) in [
+            (BY_HEIGHT_NAMESPACE, now.height),
+            (BY_TIME_NAMESPACE, now.time),
+        ]
+        .iter()
+        {
+            let prefix = key_prefix_nested(&[&self.namespace, index]);
+            // all index entries with expiry <= current, unbounded if current is the maximum
+            let end = current.checked_add(1).map(|e| e.to_be_bytes());
+            let remaining = limit as usize - stale.len();
+            let expired: StdResult<Vec<_>> = range_with_prefix(
+                self.storage,
+                &prefix,
+                None,
+                end.as_ref().map(|e| &e[..]),
+                Order::Ascending,
+            )
+            .take(remaining)
+            .collect();
+            stale.extend(
+                expired?
+                    .into_iter()
+                    .map(|(index_key, key)| (prefix.clone(), index_key, key)),
+            );
+        }
+
+        for (prefix, index_key, key) in stale.iter() {
+            remove_with_prefix(self.storage, prefix, index_key)?;
+            remove_with_prefix(self.storage, &self.data_prefix, key)?;
+        }
+        Ok(stale.len() as u32)
+    }
+
+    // remove_from_index deletes the expiry index entry of the currently stored value
+    fn remove_from_index(&mut self, key: &[u8]) -> Result<()> {
+        let old = may_load_entry::<_, T>(self.storage, &self.data_prefix, key)?;
+        if let Some((index, index_key)) = old.and_then(|e| e.expires.index_entry(key)) {
+            let prefix = key_prefix_nested(&[&self.namespace, index]);
+            remove_with_prefix(self.storage, &prefix, &index_key)?;
+        }
+        Ok(())
+    }
+}
+
+/// ReadonlyExpiringBucket only requires a ReadonlyStorage and exposes only the
+/// methods of ExpiringBucket that don't modify state.
+pub struct ReadonlyExpiringBucket<'a, S: ReadonlyStorage, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    storage: &'a S,
+    data_prefix: Vec<u8>,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+}
+
+impl<'a, S: ReadonlyStorage, T> ReadonlyExpiringBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(namespace: &[u8], storage: &'a S) -> Self {
+        ReadonlyExpiringBucket {
+            storage,
+            data_prefix: key_prefix_nested(&[namespace, DATA_NAMESPACE]),
+            data: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a S) -> Result<Self> {
+        try_key_prefix(namespace)?;
+        Ok(Self::new(namespace, storage))
+    }
+
+    /// load returns NotFound if there is no value at the key or it is expired
+    pub fn load(&self, key: &[u8], now: &BlockTime) -> Result<T> {
+        load::<_, T>(self.storage, &self.data_prefix, key, now)
+    }
+
+    /// may_load returns Ok(None) if there is no value at the key or it is expired
+    pub fn may_load(&self, key: &[u8], now: &BlockTime) -> Result<Option<T>> {
+        may_load::<_, T>(self.storage, &self.data_prefix, key, now)
+    }
+
+    /// range iterates over all entries that are not expired with start <= key < end
+    /// (unbounded if None) in the given order. See Bucket::range
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        now: &BlockTime,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'b> {
+        range(self.storage, &self.data_prefix, *now, start, end, order)
+    }
+}
+
+fn may_load_entry<S: ReadonlyStorage, T>(
+    storage: &S,
+    data_prefix: &[u8],
+    key: &[u8],
+) -> Result<Option<Entry<T>>>
+where
+    T: DeserializeOwned,
+{
+    let value = get_with_prefix(storage, data_prefix, key)?;
+    may_deserialize::<Json, _>(data_prefix, key, &value)
+}
+
+fn may_load<S: ReadonlyStorage, T>(
+    storage: &S,
+    data_prefix: &[u8],
+    key: &[u8],
+    now: &BlockTime,
+) -> Result<Option<T>>
+where
+    T: DeserializeOwned,
+{
+    let entry = may_load_entry::<_, T>(storage, data_prefix, key)?;
+    Ok(entry
+        .filter(|e| !e.expires.is_expired(now))
+        .map(|e| e.value))
+}
+
+fn load<S: ReadonlyStorage, T>(
+    storage: &S,
+    data_prefix: &[u8],
+    key: &[u8],
+    now: &BlockTime,
+) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    match may_load(storage, data_prefix, key, now)? {
+        Some(value) => Ok(value),
+        None => NotFound {
+            kind: type_name::<T>(),
+            namespace: data_prefix.to_vec(),
+            key: key.to_vec(),
+        }
+        .fail(),
+    }
+}
+
+#[cfg(feature = "iterator")]
+fn range<'a, S: ReadonlyStorage, T>(
+    storage: &'a S,
+    data_prefix: &'a [u8],
+    now: BlockTime,
+    start: Option<&[u8]>,
+    end: Option<&[u8]>,
+    order: Order,
+) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'a>
+where
+    T: DeserializeOwned + 'a,
+{
+    let mapped = range_with_prefix(storage, data_prefix, start, end, order)
+        .map(move |kv| deserialize_kv::<Json, Entry<T>>(data_prefix, kv))
+        .filter_map(move |item| match item {
+            Ok((_, ref entry)) if entry.expires.is_expired(&now) => None,
+            Ok((k, entry)) => Some(Ok((k, entry.value))),
+            Err(e) => Some(Err(e)),
+        });
+    Box::new(mapped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+
+    const NOW: BlockTime = BlockTime {
+        height: 100,
+        time: 1_000_000,
+    };
+
+    fn later(blocks: u64) -> BlockTime {
+        BlockTime {
+            height: NOW.height + blocks,
+            time: NOW.time + blocks * 5,
+        }
+    }
+
+    #[test]
+    fn expired_entries_are_hidden() {
+        let mut store = MockStorage::new();
+        let mut approvals = expiring_bucket::<_, u64>(b"approvals", &mut store);
+
+        approvals
+            .save(b"alice", &10, Expiration::AtHeight(105))
+            .unwrap();
+        approvals
+            .save(b"bob", &20, Expiration::AtTime(NOW.time + 10))
+            .unwrap();
+        approvals.save(b"carl", &30, Expiration::Never).unwrap();
+
+        assert_eq!(approvals.load(b"alice", &NOW).unwrap(), 10);
+        assert_eq!(approvals.may_load(b"alice", &later(4)).unwrap(), Some(10));
+        assert_eq!(approvals.may_load(b"alice", &later(5)).unwrap(), None);
+        assert_eq!(approvals.may_load(b"bob", &later(1)).unwrap(), Some(20));
+        assert_eq!(approvals.may_load(b"bob", &later(2)).unwrap(), None);
+        assert_eq!(approvals.load(b"carl", &later(1000)).unwrap(), 30);
+
+        let err = approvals.load(b"alice", &later(5)).unwrap_err();
+        assert_eq!(err.to_string(), "u64 not found in approvals/data at alice");
+
+        let reader = expiring_bucket_read::<_, u64>(b"approvals", &store);
+        assert_eq!(reader.may_load(b"bob", &NOW).unwrap(), Some(20));
+        assert_eq!(reader.may_load(b"bob", &later(2)).unwrap(), None);
+        assert!(reader.load(b"dave", &NOW).is_err());
+    }
+
+    #[test]
+    fn save_replaces_expiration() {
+        let mut store = MockStorage::new();
+        let mut approvals = expiring_bucket::<_, u64>(b"approvals", &mut store);
+
+        approvals
+            .save(b"alice", &10, Expiration::AtHeight(101))
+            .unwrap();
+        approvals
+            .save(b"alice", &11, Expiration::AtHeight(200))
+            .unwrap();
+        assert_eq!(approvals.load(b"alice", &later(10)).unwrap(), 11);
+
+        approvals.remove(b"alice").unwrap();
+        assert_eq!(approvals.may_load(b"alice", &NOW).unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_skips_expired() {
+        let mut store = MockStorage::new();
+        let mut approvals = expiring_bucket::<_, u64>(b"approvals", &mut store);
+        approvals.save(b"a", &1, Expiration::AtHeight(102)).unwrap();
+        approvals.save(b"b", &2, Expiration::Never).unwrap();
+        approvals.save(b"c", &3, Expiration::AtHeight(101)).unwrap();
+
+        let all: Result<Vec<_>> = approvals
+            .range(&NOW, None, None, Order::Ascending)
+            .collect();
+        assert_eq!(all.unwrap().len(), 3);
+
+        let valid: Result<Vec<_>> = expiring_bucket_read::<_, u64>(b"approvals", &store)
+            .range(&later(1), None, None, Order::Descending)
+            .collect();
+        assert_eq!(valid.unwrap(), vec![(b"b".to_vec(), 2), (b"a".to_vec(), 1)]);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn prune_in_batches() {
+        let mut store = MockStorage::new();
+        let mut approvals = expiring_bucket::<_, u64>(b"approvals", &mut store);
+        for i in 0..5u64 {
+            let key = [b'h', b'0' + i as u8];
+            approvals
+                .save(&key, &i, Expiration::AtHeight(NOW.height + i))
+                .unwrap();
+        }
+        approvals
+            .save(b"t", &9, Expiration::AtTime(NOW.time))
+            .unwrap();
+        approvals.save(b"n", &7, Expiration::Never).unwrap();
+        // moving the expiration leaves no stale index entry
+        approvals
+            .save(b"h0", &0, Expiration::AtHeight(NOW.height + 50))
+            .unwrap();
+
+        let now = later(2);
+        assert_eq!(approvals.prune_expired(&now, 2).unwrap(), 2);
+        assert_eq!(approvals.prune_expired(&now, 2).unwrap(), 1);
+        assert_eq!(approvals.prune_expired(&now, 2).unwrap(), 0);
+
+        let left: Result<Vec<_>> = approvals
+            .range(&NOW, None, None, Order::Ascending)
+            .map(|item| item.map(|(k, _)| k))
+            .collect();
+        assert_eq!(
+            left.unwrap(),
+            vec![
+                b"h0".to_vec(),
+                b"h3".to_vec(),
+                b"h4".to_vec(),
+                b"n".to_vec()
+            ]
+        );
+
+        // nothing expires at the end of time, but entries reaching it are pruned
+        let end = BlockTime {
+            height: std::u64::MAX,
+            time: std::u64::MAX,
+        };
+        assert_eq!(approvals.prune_expired(&end, 10).unwrap(), 3);
+        assert_eq!(approvals.load(b"n", &end).unwrap(), 7);
+    }
+}
@@ -0,0 +1,739 @@
+use serde::{de::DeserializeOwned, ser::Serialize};
+
+#[cfg(feature = "iterator")]
+use cosmwasm_std::{Order, StdResult};
+use cosmwasm_std::{ReadonlyStorage, Storage};
+
+use crate::bucket::{Bucket, ReadonlyBucket};
+use crate::codec::{Codec, Json};
+use crate::errors::{IndexConflict, InvalidIndex, Result, StorageError};
+#[cfg(feature = "iterator")]
+use crate::namespace_helpers::range_with_prefix;
+use crate::namespace_helpers::{
+    get_with_prefix, key_prefix_nested, remove_with_prefix, set_with_prefix, try_key_prefix,
+};
+
+// the data and every index live in their own sub-namespace of the bucket namespace,
+// so index entries can never collide with primary keys or entries of other indexes
+const DATA_NAMESPACE: &[u8] = b"pk";
+const INDEX_NAMESPACE: &[u8] = b"idx";
+
+pub fn indexed_bucket<'a, S: Storage, T>(
+    namespace: &[u8],
+    storage: &'a mut S,
+    indexes: Vec<Index<T>>,
+) -> IndexedBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    IndexedBucket::new(namespace, storage, indexes)
+}
+
+pub fn indexed_bucket_read<'a, S: ReadonlyStorage, T>(
+    namespace: &[u8],
+    storage: &'a S,
+) -> ReadonlyIndexedBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    ReadonlyIndexedBucket::new(namespace, storage)
+}
+
+/// Index defines a secondary index on a bucket. index_fn calculates the index value for every
+/// stored item. For indexes created with `new`, many items may share the same index value,
+/// while `unique` indexes map every value to exactly one primary key.
+///
+/// Index values are stored as namespaces, so they may be at most 0xFFFF bytes long.
+/// Saving an item with a longer index value returns an error
+pub struct Index<T> {
+    name: Vec<u8>,
+    index_fn: fn(&T) -> Vec<u8>,
+    unique: bool,
+}
+
+impl<T> Index<T> {
+    pub fn new(name: &[u8], index_fn: fn(&T) -> Vec<u8>) -> Self {
+        Index {
+            name: name.to_vec(),
+            index_fn,
+            unique: false,
+        }
+    }
+
+    /// unique creates an index where every value can only be claimed by one primary key.
+    /// Saving an item whose value is already used by another key returns an error
+    pub fn unique(name: &[u8], index_fn: fn(&T) -> Vec<u8>) -> Self {
+        Index {
+            name: name.to_vec(),
+            index_fn,
+            unique: true,
+        }
+    }
+
+    // entry_key returns the key of the index entry for the given item, relative to the index prefix.
+    // Unique indexes store the value itself (pointing to the primary key), while other indexes
+    // append the primary key to the length-prefixed value, so all primary keys for one value
+    // can be found with a prefix scan
+    fn entry_key(&self, data: &T, pk: &[u8]) -> Result<Vec<u8>> {
+        let value = (self.index_fn)(data);
+        if self.unique {
+            Ok(value)
+        } else {
+            let mut key = try_key_prefix(&value)?;
+            key.extend_from_slice(pk);
+            Ok(key)
+        }
+    }
+
+    fn prefix(&self, namespace: &[u8]) -> Vec<u8> {
+        key_prefix_nested(&[namespace, INDEX_NAMESPACE, &self.name])
+    }
+}
+
+/// IndexedBucket works like a Bucket, but keeps a number of secondary indexes in sync with
+/// the stored data on every save, update and remove. The indexes can be queried with `index`
+/// (or `load_unique`), passing the same Index definition.
+pub struct IndexedBucket<'a, S: Storage, T, C = Json>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    bucket: Bucket<'a, S, T, C>,
+    namespace: Vec<u8>,
+    indexes: Vec<Index<T>>,
+}
+
+impl<'a, S: Storage, T, C> IndexedBucket<'a, S, T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    pub fn new(namespace: &[u8], storage: &'a mut S, indexes: Vec<Index<T>>) -> Self {
+        IndexedBucket {
+            bucket: Bucket::multilevel(&[namespace, DATA_NAMESPACE], storage),
+            namespace: namespace.to_vec(),
+            indexes,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a mut S, indexes: Vec<Index<T>>) -> Result<Self> {
+        Ok(IndexedBucket {
+            bucket: Bucket::try_multilevel(&[namespace, DATA_NAMESPACE], storage)?,
+            namespace: namespace.to_vec(),
+            indexes,
+        })
+    }
+
+    /// save will serialize the model and store it, updating all indexes.
+    /// Returns an error on serialization issues, if an index value is too long, or if a
+    /// unique index value is already claimed by another key. Nothing is written in these cases
+    pub fn save(&mut self, key: &[u8], data: &T) -> Result<()> {
+        // calculate all index entries and check the unique indexes before writing anything
+        let mut entries = Vec::with_capacity(self.indexes.len());
+        for index in self.indexes.iter() {
+            let prefix = index.prefix(&self.namespace);
+            let entry_key = index.entry_key(data, key)?;
+            if index.unique {
+                match get_with_prefix(self.bucket.storage(), &prefix, &entry_key)? {
+                    Some(pk) if pk != key => {
+                        return IndexConflict {
+                            index: index_name(&index.name),
+                            value: entry_key,
+                        }
+                        .fail()
+                    }
+                    _ => {}
+                }
+            }
+            entries.push((prefix, entry_key));
+        }
+        let old_entries = match self.may_load(key)? {
+            Some(old) => self.entry_keys(key, &old)?,
+            None => vec![],
+        };
+
+        // this encodes the data, so serialization errors are returned before touching the indexes
+        self.bucket.save(key, data)?;
+        let storage = self.bucket.storage_mut();
+        for (prefix, entry_key) in old_entries {
+            remove_with_prefix(storage, &prefix, &entry_key)?;
+        }
+        for (prefix, entry_key) in entries {
+            set_with_prefix(storage, &prefix, &entry_key, key)?;
+        }
+        Ok(())
+    }
+
+    /// remove will delete the data stored at the key along with its index entries.
+    /// Removing a missing key is a no-op. Returns an error if the old data cannot be parsed
+    pub fn remove(&mut self, key: &[u8]) -> Result<()> {
+        if let Some(old) = self.may_load(key)? {
+            let old_entries = self.entry_keys(key, &old)?;
+            let storage = self.bucket.storage_mut();
+            for (prefix, entry_key) in old_entries {
+                remove_with_prefix(storage, &prefix, &entry_key)?;
+            }
+            self.bucket.remove(key)?;
+        }
+        Ok(())
+    }
+
+    // entry_keys returns the prefix and key of every index entry of the item
+    fn entry_keys(&self, key: &[u8], data: &T) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.indexes
+            .iter()
+            .map(|index| Ok((index.prefix(&self.namespace), index.entry_key(data, key)?)))
+            .collect()
+    }
+
+    /// load will return an error if no data is set at the given key, or on parse error
+    pub fn load(&self, key: &[u8]) -> Result<T> {
+        self.bucket.load(key)
+    }
+
+    /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self, key: &[u8]) -> Result<Option<T>> {
+        self.bucket.may_load(key)
+    }
+
+    /// range iterates over all entries in primary key order, see Bucket::range
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'b> {
+        self.bucket.range(start, end, order)
+    }
+
+    /// index returns a reader to query the given index. The index must be one of the indexes
+    /// this bucket was created with, otherwise its entries are not maintained
+    #[cfg(feature = "iterator")]
+    pub fn index(&self, index: &Index<T>) -> IndexReader<'_, S, T, C> {
+        IndexReader::new(self.bucket.storage(), &self.namespace, index)
+    }
+
+    /// load_unique looks up the item claiming the given value in a unique index,
+    /// returning its primary key and data, or Ok(None) if the value is not claimed.
+    /// Returns an error if the index is not unique
+    pub fn load_unique(&self, index: &Index<T>, value: &[u8]) -> Result<Option<(Vec<u8>, T)>> {
+        let data = ReadonlyBucket::<_, T, C>::multilevel(
+            &[&self.namespace, DATA_NAMESPACE],
+            self.bucket.storage(),
+        );
+        load_unique(&data, &self.namespace, index, value)
+    }
+
+    /// update will load the data, perform the specified action, and store the result
+    /// in the database, updating all indexes. See Bucket::update
+    pub fn update<A, E>(&mut self, key: &[u8], action: A) -> Result<T, E>
+    where
+        A: FnOnce(T) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.load(key)?;
+        let output = action(input)?;
+        self.save(key, &output)?;
+        Ok(output)
+    }
+
+    /// may_update is like update, but passes None to the action if there is no data.
+    /// See Bucket::may_update
+    pub fn may_update<A, E>(&mut self, key: &[u8], action: A) -> Result<T, E>
+    where
+        A: FnOnce(Option<T>) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.may_load(key)?;
+        let output = action(input)?;
+        self.save(key, &output)?;
+        Ok(output)
+    }
+}
+
+/// ReadonlyIndexedBucket reads the data and indexes written by an IndexedBucket. It doesn't
+/// need the index definitions up front, they are passed to `index` and `load_unique`
+pub struct ReadonlyIndexedBucket<'a, S: ReadonlyStorage, T, C = Json>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    bucket: ReadonlyBucket<'a, S, T, C>,
+    namespace: Vec<u8>,
+}
+
+impl<'a, S: ReadonlyStorage, T, C> ReadonlyIndexedBucket<'a, S, T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    pub fn new(namespace: &[u8], storage: &'a S) -> Self {
+        ReadonlyIndexedBucket {
+            bucket: ReadonlyBucket::multilevel(&[namespace, DATA_NAMESPACE], storage),
+            namespace: namespace.to_vec(),
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a S) -> Result<Self> {
+        Ok(ReadonlyIndexedBucket {
+            bucket: ReadonlyBucket::try_multilevel(&[namespace, DATA_NAMESPACE], storage)?,
+            namespace: namespace.to_vec(),
+        })
+    }
+
+    /// load will return an error if no data is set at the given key, or on parse error
+    pub fn load(&self, key: &[u8]) -> Result<T> {
+        self.bucket.load(key)
+    }
+
+    /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self, key: &[u8]) -> Result<Option<T>> {
+        self.bucket.may_load(key)
+    }
+
+    /// range iterates over all entries in primary key order, see Bucket::range
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'b> {
+        self.bucket.range(start, end, order)
+    }
+
+    /// index returns a reader to query the given index, see IndexedBucket::index
+    #[cfg(feature = "iterator")]
+    pub fn index(&self, index: &Index<T>) -> IndexReader<'a, S, T, C> {
+        IndexReader::new(self.bucket.storage(), &self.namespace, index)
+    }
+
+    /// load_unique looks up the item claiming the given value in a unique index,
+    /// returning its primary key and data, or Ok(None) if the value is not claimed.
+    /// Returns an error if the index is not unique
+    pub fn load_unique(&self, index: &Index<T>, value: &[u8]) -> Result<Option<(Vec<u8>, T)>> {
+        load_unique(&self.bucket, &self.namespace, index, value)
+    }
+}
+
+/// IndexReader queries one secondary index of an IndexedBucket
+#[cfg(feature = "iterator")]
+pub struct IndexReader<'a, S: ReadonlyStorage, T, C = Json>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    data: ReadonlyBucket<'a, S, T, C>,
+    index_prefix: Vec<u8>,
+    unique: bool,
+}
+
+#[cfg(feature = "iterator")]
+impl<'a, S: ReadonlyStorage, T, C> IndexReader<'a, S, T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    fn new(storage: &'a S, namespace: &[u8], index: &Index<T>) -> Self {
+        IndexReader {
+            data: ReadonlyBucket::multilevel(&[namespace, DATA_NAMESPACE], storage),
+            index_prefix: index.prefix(namespace),
+            unique: index.unique,
+        }
+    }
+
+    /// range iterates over all entries with the given index value, returning their primary keys
+    /// and the loaded data. start and end bound the primary keys (unbounded if None).
+    /// For unique indexes, this returns at most one entry
+    pub fn range<'b>(
+        &'b self,
+        value: &[u8],
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'b> {
+        let storage = self.data.storage();
+        let pks: Box<dyn Iterator<Item = StdResult<Vec<u8>>> + 'b> = if self.unique {
+            let start = start.map(|s| s.to_vec());
+            let end = end.map(|e| e.to_vec());
+            let pk = match get_with_prefix(storage, &self.index_prefix, value) {
+                Ok(pk) => pk,
+                Err(e) => return Box::new(std::iter::once(Err(e.into()))),
+            };
+            let pk =
+                pk.filter(move |pk| start.iter().all(|s| pk >= s) && end.iter().all(|e| pk < e));
+            Box::new(pk.into_iter().map(Ok))
+        } else {
+            let mut prefix = self.index_prefix.clone();
+            match try_key_prefix(value) {
+                Ok(value_prefix) => prefix.extend_from_slice(&value_prefix),
+                Err(e) => return Box::new(std::iter::once(Err(e))),
+            }
+            let kvs = range_with_prefix(storage, &prefix, start, end, order);
+            Box::new(kvs.map(|kv| kv.map(|(pk, _)| pk)))
+        };
+        let data = &self.data;
+        Box::new(pks.map(move |pk| {
+            let pk = pk?;
+            let item = data.load(&pk)?;
+            Ok((pk, item))
+        }))
+    }
+}
+
+fn load_unique<S: ReadonlyStorage, T, C>(
+    data: &ReadonlyBucket<S, T, C>,
+    namespace: &[u8],
+    index: &Index<T>,
+    value: &[u8],
+) -> Result<Option<(Vec<u8>, T)>>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    if !index.unique {
+        return InvalidIndex {
+            index: index_name(&index.name),
+            msg: "index is not unique",
+        }
+        .fail();
+    }
+    match get_with_prefix(data.storage(), &index.prefix(namespace), value)? {
+        Some(pk) => {
+            let item = data.load(&pk)?;
+            Ok(Some((pk, item)))
+        }
+        None => Ok(None),
+    }
+}
+
+// index_name makes the index name printable for error messages
+fn index_name(name: &[u8]) -> String {
+    String::from_utf8_lossy(name).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+    use serde::{Deserialize, Serialize};
+
+    use crate::namespace_helpers::key_prefix;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct Offer {
+        pub owner: String,
+        pub denom: String,
+        pub amount: u64,
+    }
+
+    fn offer(owner: &str, denom: &str, amount: u64) -> Offer {
+        Offer {
+            owner: owner.to_string(),
+            denom: denom.to_string(),
+            amount,
+        }
+    }
+
+    fn by_owner() -> Index<Offer> {
+        Index::new(b"owner", |o| o.owner.as_bytes().to_vec())
+    }
+
+    fn by_denom() -> Index<Offer> {
+        Index::new(b"denom", |o| o.denom.as_bytes().to_vec())
+    }
+
+    fn indexes() -> Vec<Index<Offer>> {
+        vec![by_owner(), by_denom()]
+    }
+
+    #[test]
+    fn store_and_load() {
+        let mut store = MockStorage::new();
+        let mut offers = indexed_bucket(b"offers", &mut store, indexes());
+
+        let data = offer("alice", "atom", 100);
+        offers.save(b"1", &data).unwrap();
+        assert_eq!(offers.load(b"1").unwrap(), data);
+        assert_eq!(offers.may_load(b"2").unwrap(), None);
+
+        let reader = indexed_bucket_read::<_, Offer>(b"offers", &store);
+        assert_eq!(reader.load(b"1").unwrap(), data);
+
+        let mut offers = indexed_bucket(b"offers", &mut store, indexes());
+        offers.remove(b"1").unwrap();
+        assert_eq!(offers.may_load(b"1").unwrap(), None);
+        // removing twice is fine
+        offers.remove(b"1").unwrap();
+    }
+
+    #[test]
+    fn indexes_dont_collide_with_data() {
+        let mut store = MockStorage::new();
+        let mut offers = indexed_bucket(b"offers", &mut store, indexes());
+        offers.save(b"1", &offer("alice", "atom", 100)).unwrap();
+
+        // data and index entries live in separate sub-namespaces
+        let raw = crate::prefixed_read(b"offers", &store);
+        for key in &[
+            [key_prefix(DATA_NAMESPACE), b"1".to_vec()].concat(),
+            [
+                key_prefix_nested(&[INDEX_NAMESPACE, b"owner", b"alice"]),
+                b"1".to_vec(),
+            ]
+            .concat(),
+            [
+                key_prefix_nested(&[INDEX_NAMESPACE, b"denom", b"atom"]),
+                b"1".to_vec(),
+            ]
+            .concat(),
+        ] {
+            assert!(raw.get(key).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn query_by_index() {
+        let mut store = MockStorage::new();
+        let mut offers = indexed_bucket(b"offers", &mut store, indexes());
+
+        offers.save(b"1", &offer("alice", "atom", 100)).unwrap();
+        offers.save(b"2", &offer("bob", "atom", 200)).unwrap();
+        offers.save(b"3", &offer("alice", "btc", 300)).unwrap();
+        offers.save(b"4", &offer("alicia", "btc", 400)).unwrap();
+
+        let by_owner = offers.index(&by_owner());
+        let alice: Result<Vec<(Vec<u8>, Offer)>> = by_owner
+            .range(b"alice", None, None, Order::Ascending)
+            .collect();
+        let alice = alice.unwrap();
+        assert_eq!(alice.len(), 2);
+        assert_eq!(alice[0], (b"1".to_vec(), offer("alice", "atom", 100)));
+        assert_eq!(alice[1], (b"3".to_vec(), offer("alice", "btc", 300)));
+
+        // primary key bounds and order
+        let alice: Result<Vec<(Vec<u8>, Offer)>> = by_owner
+            .range(b"alice", None, Some(b"3"), Order::Descending)
+            .collect();
+        assert_eq!(
+            alice.unwrap(),
+            vec![(b"1".to_vec(), offer("alice", "atom", 100))]
+        );
+
+        let reader = indexed_bucket_read::<_, Offer>(b"offers", &store);
+        let btc: Result<Vec<(Vec<u8>, Offer)>> = reader
+            .index(&by_denom())
+            .range(b"btc", None, None, Order::Ascending)
+            .collect();
+        let keys: Vec<Vec<u8>> = btc.unwrap().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"3".to_vec(), b"4".to_vec()]);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn indexes_follow_changes() {
+        let mut store = MockStorage::new();
+        let mut offers = indexed_bucket(b"offers", &mut store, indexes());
+
+        offers.save(b"1", &offer("alice", "atom", 100)).unwrap();
+        offers.save(b"2", &offer("alice", "atom", 200)).unwrap();
+
+        // moving an order to another owner updates the index
+        offers.save(b"1", &offer("bob", "atom", 100)).unwrap();
+        // update goes through save as well
+        offers
+            .update(b"2", |mut o| -> Result<_> {
+                o.denom = "btc".to_string();
+                Ok(o)
+            })
+            .unwrap();
+
+        let count =
+            |offers: &IndexedBucket<MockStorage, Offer>, index: Index<Offer>, value: &[u8]| {
+                offers
+                    .index(&index)
+                    .range(value, None, None, Order::Ascending)
+                    .count()
+            };
+        assert_eq!(count(&offers, by_owner(), b"alice"), 1);
+        assert_eq!(count(&offers, by_owner(), b"bob"), 1);
+        assert_eq!(count(&offers, by_denom(), b"atom"), 1);
+        assert_eq!(count(&offers, by_denom(), b"btc"), 1);
+
+        // removing cleans up the index entries
+        offers.remove(b"1").unwrap();
+        offers.remove(b"2").unwrap();
+        assert_eq!(count(&offers, by_owner(), b"alice"), 0);
+        assert_eq!(count(&offers, by_owner(), b"bob"), 0);
+        assert_eq!(count(&offers, by_denom(), b"atom"), 0);
+        assert_eq!(count(&offers, by_denom(), b"btc"), 0);
+
+        let all: Vec<_> = offers.range(None, None, Order::Ascending).collect();
+        assert!(all.is_empty());
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct Account {
+        pub username: String,
+        pub email: String,
+    }
+
+    fn account(username: &str, email: &str) -> Account {
+        Account {
+            username: username.to_string(),
+            email: email.to_string(),
+        }
+    }
+
+    fn by_username() -> Index<Account> {
+        Index::unique(b"username", |a| a.username.as_bytes().to_vec())
+    }
+
+    fn by_email() -> Index<Account> {
+        Index::new(b"email", |a| a.email.as_bytes().to_vec())
+    }
+
+    fn account_indexes() -> Vec<Index<Account>> {
+        vec![by_username(), by_email()]
+    }
+
+    #[test]
+    fn unique_index_lookup() {
+        let mut store = MockStorage::new();
+        let mut accounts = indexed_bucket(b"accounts", &mut store, account_indexes());
+
+        let alice = account("alice", "alice@example.com");
+        accounts.save(b"1", &alice).unwrap();
+        let found = accounts.load_unique(&by_username(), b"alice").unwrap();
+        assert_eq!(found, Some((b"1".to_vec(), alice.clone())));
+        assert_eq!(accounts.load_unique(&by_username(), b"bob").unwrap(), None);
+
+        // renaming frees the old name
+        accounts
+            .save(b"1", &account("alicia", "alice@example.com"))
+            .unwrap();
+        assert_eq!(
+            accounts.load_unique(&by_username(), b"alice").unwrap(),
+            None
+        );
+        let reader = indexed_bucket_read::<_, Account>(b"accounts", &store);
+        let found = reader.load_unique(&by_username(), b"alicia").unwrap();
+        assert_eq!(found.unwrap().0, b"1".to_vec());
+
+        // only unique indexes can be used
+        assert!(reader
+            .load_unique(&by_email(), b"alice@example.com")
+            .is_err());
+    }
+
+    #[test]
+    fn unique_index_conflict() {
+        let mut store = MockStorage::new();
+        let mut accounts = indexed_bucket(b"accounts", &mut store, account_indexes());
+
+        let alice = account("alice", "alice@example.com");
+        accounts.save(b"1", &alice).unwrap();
+        // saving the same key again is fine
+        accounts.save(b"1", &alice).unwrap();
+
+        // another key must not claim the name
+        let err = accounts
+            .save(b"2", &account("alice", "other@example.com"))
+            .unwrap_err();
+        match err {
+            StorageError::IndexConflict { index, value } => {
+                assert_eq!(index, "username");
+                assert_eq!(value, b"alice".to_vec());
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+        let res = accounts.may_update(b"2", |_| -> Result<_> {
+            Ok(account("alice", "bob@example.com"))
+        });
+        assert!(res.is_err());
+
+        // nothing was written
+        assert_eq!(accounts.may_load(b"2").unwrap(), None);
+        let found = accounts.load_unique(&by_username(), b"alice").unwrap();
+        assert_eq!(found, Some((b"1".to_vec(), alice)));
+
+        // once released, the name can be claimed
+        accounts.remove(b"1").unwrap();
+        accounts
+            .save(b"2", &account("alice", "other@example.com"))
+            .unwrap();
+        let found = accounts.load_unique(&by_username(), b"alice").unwrap();
+        assert_eq!(found.unwrap().0, b"2".to_vec());
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn query_unique_index() {
+        let mut store = MockStorage::new();
+        let mut accounts = indexed_bucket(b"accounts", &mut store, account_indexes());
+        accounts
+            .save(b"1", &account("alice", "alice@example.com"))
+            .unwrap();
+
+        let by_name = accounts.index(&by_username());
+        let found: Result<Vec<_>> = by_name
+            .range(b"alice", None, None, Order::Ascending)
+            .collect();
+        assert_eq!(found.unwrap().len(), 1);
+        // the bounds apply to the primary key
+        assert_eq!(
+            by_name
+                .range(b"alice", Some(b"2"), None, Order::Ascending)
+                .count(),
+            0
+        );
+        assert_eq!(
+            by_name.range(b"bob", None, None, Order::Ascending).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn save_fails_before_writing() {
+        let mut store = MockStorage::new();
+        let mut offers = indexed_bucket(b"offers", &mut store, indexes());
+        offers.save(b"1", &offer("alice", "atom", 100)).unwrap();
+
+        // the owner is too long for an index value
+        let long_owner = "x".repeat(0x10000);
+        let err = offers
+            .save(b"1", &offer(&long_owner, "btc", 200))
+            .unwrap_err();
+        assert_eq!(err, StorageError::NamespaceTooLong { length: 0x10000 });
+
+        // neither the data nor the indexes changed
+        assert_eq!(offers.load(b"1").unwrap(), offer("alice", "atom", 100));
+        let raw = crate::prefixed_read(b"offers", &store);
+        assert!(raw
+            .get(
+                &[
+                    key_prefix_nested(&[INDEX_NAMESPACE, b"owner", b"alice"]),
+                    b"1".to_vec()
+                ]
+                .concat()
+            )
+            .unwrap()
+            .is_some());
+        assert!(raw
+            .get(
+                &[
+                    key_prefix_nested(&[INDEX_NAMESPACE, b"denom", b"btc"]),
+                    b"1".to_vec()
+                ]
+                .concat()
+            )
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,555 @@
+use serde::{de::DeserializeOwned, ser::Serialize};
+use std::marker::PhantomData;
+
+#[cfg(feature = "iterator")]
+use cosmwasm_std::Order;
+use cosmwasm_std::{ReadonlyStorage, Storage};
+
+use crate::bucket::{Bucket, ReadonlyBucket};
+use crate::errors::{Result, StorageError};
+use crate::keys::{joined_prefix, CompositeKey, PrimaryKey};
+#[cfg(feature = "iterator")]
+use crate::pagination::{Page, Pagination};
+
+pub fn keyed_bucket<'a, S: Storage, K, T>(
+    namespace: &[u8],
+    storage: &'a mut S,
+) -> KeyedBucket<'a, S, K, T>
+where
+    K: PrimaryKey,
+    T: Serialize + DeserializeOwned,
+{
+    KeyedBucket::new(namespace, storage)
+}
+
+pub fn keyed_bucket_read<'a, S: ReadonlyStorage, K, T>(
+    namespace: &[u8],
+    storage: &'a S,
+) -> ReadonlyKeyedBucket<'a, S, K, T>
+where
+    K: PrimaryKey,
+    T: Serialize + DeserializeOwned,
+{
+    ReadonlyKeyedBucket::new(namespace, storage)
+}
+
+/// KeyedBucket works just like Bucket, but takes typed keys (anything implementing PrimaryKey)
+/// rather than raw bytes. Keys returned from range are parsed back into the typed form.
+pub struct KeyedBucket<'a, S: Storage, K, T>
+where
+    K: PrimaryKey,
+    T: Serialize + DeserializeOwned,
+{
+    bucket: Bucket<'a, S, T>,
+    key: PhantomData<K>,
+}
+
+impl<'a, S: Storage, K, T> KeyedBucket<'a, S, K, T>
+where
+    K: PrimaryKey,
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(namespace: &[u8], storage: &'a mut S) -> Self {
+        KeyedBucket {
+            bucket: Bucket::new(namespace, storage),
+            key: PhantomData,
+        }
+    }
+
+    pub fn multilevel(namespaces: &[&[u8]], storage: &'a mut S) -> Self {
+        KeyedBucket {
+            bucket: Bucket::multilevel(namespaces, storage),
+            key: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a mut S) -> Result<Self> {
+        Ok(KeyedBucket {
+            bucket: Bucket::try_new(namespace, storage)?,
+            key: PhantomData,
+        })
+    }
+
+    /// try_multilevel works like multilevel, but returns an error if any namespace is longer
+    /// than 0xFFFF bytes
+    pub fn try_multilevel(namespaces: &[&[u8]], storage: &'a mut S) -> Result<Self> {
+        Ok(KeyedBucket {
+            bucket: Bucket::try_multilevel(namespaces, storage)?,
+            key: PhantomData,
+        })
+    }
+
+    /// save will serialize the model and store, returns an error on serialization issues
+    pub fn save(&mut self, key: K, data: &T) -> Result<()> {
+        self.bucket.save(&key.joined_key()?, data)
+    }
+
+    /// remove will delete the data stored at the key. Removing a missing key is a no-op
+    pub fn remove(&mut self, key: K) -> Result<()> {
+        // a key that cannot be encoded was never saved, so there is nothing to remove
+        match key.joined_key() {
+            Ok(key) => self.bucket.remove(&key),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// load will return an error if no data is set at the given key, or on parse error
+    pub fn load(&self, key: K) -> Result<T> {
+        self.bucket.load(&key.joined_key()?)
+    }
+
+    /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self, key: K) -> Result<Option<T>> {
+        self.bucket.may_load(&key.joined_key()?)
+    }
+
+    /// range iterates over all entries with start <= key < end (unbounded if None) in the given order.
+    /// Keys are parsed back into K::Output
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        start: Option<K>,
+        end: Option<K>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(K::Output, T)>> + 'b> {
+        let (start, end) = match (joined_bound(start), joined_bound(end)) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(e), _) | (_, Err(e)) => return Box::new(std::iter::once(Err(e))),
+        };
+        let mapped = self
+            .bucket
+            .range(
+                start.as_ref().map(|k| k.as_slice()),
+                end.as_ref().map(|k| k.as_slice()),
+                order,
+            )
+            .map(parse_typed_key::<K, T>);
+        Box::new(mapped)
+    }
+
+    /// page returns up to limit entries after the start_after key (exclusive) in the given order.
+    /// See Bucket::page
+    #[cfg(feature = "iterator")]
+    pub fn page(
+        &self,
+        start_after: Option<K>,
+        limit: Option<u32>,
+        order: Order,
+        pagination: &Pagination,
+    ) -> Result<Page<K::Output, T>> {
+        let start_after = joined_bound(start_after)?;
+        self.bucket
+            .page(
+                start_after.as_ref().map(|k| k.as_slice()),
+                limit,
+                order,
+                pagination,
+            )?
+            .parse_keys(K::parse_key)
+    }
+
+    /// update will load the data, perform the specified action, and store the result
+    /// in the database. Like Bucket::update, it only works on existing data and returns
+    /// NotFound otherwise
+    pub fn update<A, E>(&mut self, key: K, action: A) -> Result<T, E>
+    where
+        A: FnOnce(T) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        self.bucket.update(&key.joined_key()?, action)
+    }
+
+    /// may_update works like update, but passes None to the action if there is no data.
+    /// See Bucket::may_update
+    pub fn may_update<A, E>(&mut self, key: K, action: A) -> Result<T, E>
+    where
+        A: FnOnce(Option<T>) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        self.bucket.may_update(&key.joined_key()?, action)
+    }
+}
+
+impl<'a, S: Storage, K, T> KeyedBucket<'a, S, K, T>
+where
+    K: CompositeKey,
+    T: Serialize + DeserializeOwned,
+{
+    /// prefix returns a KeyedBucket over all entries whose key starts with the given first component.
+    /// It is keyed by the remaining components, e.g. for (owner, spender) keys, prefix(owner)
+    /// gives access to all allowances of that owner keyed by spender.
+    ///
+    /// Panics if the prefix is longer than 0xFFFF bytes, use try_prefix for user input
+    pub fn prefix(&mut self, prefix: K::Prefix) -> KeyedBucket<'_, S, K::Suffix, T> {
+        self.try_prefix(prefix).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// try_prefix works like prefix, but returns an error if the prefix cannot be encoded
+    pub fn try_prefix(&mut self, prefix: K::Prefix) -> Result<KeyedBucket<'_, S, K::Suffix, T>> {
+        Ok(KeyedBucket {
+            bucket: self.bucket.sub_bucket(&joined_prefix(&prefix)?),
+            key: PhantomData,
+        })
+    }
+}
+
+pub struct ReadonlyKeyedBucket<'a, S: ReadonlyStorage, K, T>
+where
+    K: PrimaryKey,
+    T: Serialize + DeserializeOwned,
+{
+    bucket: ReadonlyBucket<'a, S, T>,
+    key: PhantomData<K>,
+}
+
+impl<'a, S: ReadonlyStorage, K, T> ReadonlyKeyedBucket<'a, S, K, T>
+where
+    K: PrimaryKey,
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(namespace: &[u8], storage: &'a S) -> Self {
+        ReadonlyKeyedBucket {
+            bucket: ReadonlyBucket::new(namespace, storage),
+            key: PhantomData,
+        }
+    }
+
+    pub fn multilevel(namespaces: &[&[u8]], storage: &'a S) -> Self {
+        ReadonlyKeyedBucket {
+            bucket: ReadonlyBucket::multilevel(namespaces, storage),
+            key: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a S) -> Result<Self> {
+        Ok(ReadonlyKeyedBucket {
+            bucket: ReadonlyBucket::try_new(namespace, storage)?,
+            key: PhantomData,
+        })
+    }
+
+    /// try_multilevel works like multilevel, but returns an error if any namespace is longer
+    /// than 0xFFFF bytes
+    pub fn try_multilevel(namespaces: &[&[u8]], storage: &'a S) -> Result<Self> {
+        Ok(ReadonlyKeyedBucket {
+            bucket: ReadonlyBucket::try_multilevel(namespaces, storage)?,
+            key: PhantomData,
+        })
+    }
+
+    /// load will return an error if no data is set at the given key, or on parse error
+    pub fn load(&self, key: K) -> Result<T> {
+        self.bucket.load(&key.joined_key()?)
+    }
+
+    /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self, key: K) -> Result<Option<T>> {
+        self.bucket.may_load(&key.joined_key()?)
+    }
+
+    /// range iterates over all entries with start <= key < end (unbounded if None) in the given order.
+    /// Keys are parsed back into K::Output
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        start: Option<K>,
+        end: Option<K>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(K::Output, T)>> + 'b> {
+        let (start, end) = match (joined_bound(start), joined_bound(end)) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(e), _) | (_, Err(e)) => return Box::new(std::iter::once(Err(e))),
+        };
+        let mapped = self
+            .bucket
+            .range(
+                start.as_ref().map(|k| k.as_slice()),
+                end.as_ref().map(|k| k.as_slice()),
+                order,
+            )
+            .map(parse_typed_key::<K, T>);
+        Box::new(mapped)
+    }
+
+    /// page returns up to limit entries after the start_after key (exclusive) in the given order.
+    /// See Bucket::page
+    #[cfg(feature = "iterator")]
+    pub fn page(
+        &self,
+        start_after: Option<K>,
+        limit: Option<u32>,
+        order: Order,
+        pagination: &Pagination,
+    ) -> Result<Page<K::Output, T>> {
+        let start_after = joined_bound(start_after)?;
+        self.bucket
+            .page(
+                start_after.as_ref().map(|k| k.as_slice()),
+                limit,
+                order,
+                pagination,
+            )?
+            .parse_keys(K::parse_key)
+    }
+}
+
+impl<'a, S: ReadonlyStorage, K, T> ReadonlyKeyedBucket<'a, S, K, T>
+where
+    K: CompositeKey,
+    T: Serialize + DeserializeOwned,
+{
+    /// prefix returns a ReadonlyKeyedBucket over all entries whose key starts with the given
+    /// first component. It is keyed by the remaining components (see KeyedBucket::prefix).
+    ///
+    /// Panics if the prefix is longer than 0xFFFF bytes, use try_prefix for user input
+    pub fn prefix(&self, prefix: K::Prefix) -> ReadonlyKeyedBucket<'a, S, K::Suffix, T> {
+        self.try_prefix(prefix).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// try_prefix works like prefix, but returns an error if the prefix cannot be encoded
+    pub fn try_prefix(
+        &self,
+        prefix: K::Prefix,
+    ) -> Result<ReadonlyKeyedBucket<'a, S, K::Suffix, T>> {
+        Ok(ReadonlyKeyedBucket {
+            bucket: self.bucket.sub_bucket(&joined_prefix(&prefix)?),
+            key: PhantomData,
+        })
+    }
+}
+
+// joined_bound encodes an optional key used as range bound
+#[cfg(feature = "iterator")]
+fn joined_bound<K: PrimaryKey>(key: Option<K>) -> Result<Option<Vec<u8>>> {
+    key.map(|k| k.joined_key()).transpose()
+}
+
+#[cfg(feature = "iterator")]
+fn parse_typed_key<K: PrimaryKey, T>(item: Result<(Vec<u8>, T)>) -> Result<(K::Output, T)> {
+    let (k, v) = item?;
+    Ok((K::parse_key(&k)?, v))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+    use serde::{Deserialize, Serialize};
+
+    use crate::bucket_read;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct Data {
+        pub name: String,
+        pub age: i32,
+    }
+
+    #[test]
+    fn store_and_load() {
+        let mut store = MockStorage::new();
+        let mut bucket = keyed_bucket::<_, u64, Data>(b"data", &mut store);
+
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        bucket.save(7, &data).unwrap();
+        assert_eq!(bucket.load(7).unwrap(), data);
+        assert_eq!(bucket.may_load(8).unwrap(), None);
+
+        // the same as a raw bucket with big endian keys
+        let raw = bucket_read::<_, Data>(b"data", &store);
+        assert_eq!(raw.load(&7u64.to_be_bytes()).unwrap(), data);
+
+        let reader = keyed_bucket_read::<_, u64, Data>(b"data", &store);
+        assert_eq!(reader.load(7).unwrap(), data);
+
+        let mut bucket = keyed_bucket::<_, u64, Data>(b"data", &mut store);
+        bucket.remove(7).unwrap();
+        assert_eq!(bucket.may_load(7).unwrap(), None);
+    }
+
+    #[test]
+    fn string_keys() {
+        let mut store = MockStorage::new();
+        let mut bucket = keyed_bucket::<_, &str, Data>(b"people", &mut store);
+
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        bucket.save("maria", &data).unwrap();
+        let output = bucket
+            .update("maria", |mut d| -> Result<_> {
+                d.age += 1;
+                Ok(d)
+            })
+            .unwrap();
+        assert_eq!(output.age, 43);
+        assert_eq!(bucket.load("maria").unwrap(), output);
+        // update only works on existing data
+        let missing = bucket.update("john", |d| -> Result<_> { Ok(d) });
+        match missing.unwrap_err() {
+            StorageError::NotFound { .. } => {}
+            e => panic!("Unexpected error {}", e),
+        }
+        let created = bucket
+            .may_update("john", |d| -> Result<_> {
+                assert_eq!(d, None);
+                Ok(Data {
+                    name: "John".to_string(),
+                    age: 32,
+                })
+            })
+            .unwrap();
+        assert_eq!(bucket.load("john").unwrap(), created);
+
+        let owned = keyed_bucket_read::<_, String, Data>(b"people", &store);
+        assert_eq!(owned.load("maria".to_string()).unwrap(), output);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_over_signed_keys() {
+        let mut store = MockStorage::new();
+        let mut bucket = keyed_bucket::<_, i32, Data>(b"data", &mut store);
+
+        for &age in &[15, -3, 0, 1000, -1000] {
+            let data = Data {
+                name: format!("{}", age),
+                age,
+            };
+            bucket.save(age, &data).unwrap();
+        }
+
+        let keys: Result<Vec<i32>> = bucket
+            .range(None, None, Order::Ascending)
+            .map(|item| item.map(|(k, _)| k))
+            .collect();
+        assert_eq!(keys.unwrap(), vec![-1000, -3, 0, 15, 1000]);
+
+        let reader = keyed_bucket_read::<_, i32, Data>(b"data", &store);
+        let items: Result<Vec<(i32, Data)>> = reader
+            .range(Some(-3), Some(15), Order::Descending)
+            .collect();
+        let items = items.unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].0, 0);
+        assert_eq!(items[1].0, -3);
+        assert_eq!(items[1].1.age, -3);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_over_tuple_keys() {
+        let mut store = MockStorage::new();
+        let mut bucket = keyed_bucket::<_, (&str, u8), Data>(b"data", &mut store);
+
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        bucket.save(("b", 2), &data).unwrap();
+        bucket.save(("a", 9), &data).unwrap();
+        bucket.save(("b", 1), &data).unwrap();
+
+        let keys: Result<Vec<(String, u8)>> = bucket
+            .range(None, None, Order::Ascending)
+            .map(|item| item.map(|(k, _)| k))
+            .collect();
+        let expected = vec![
+            ("a".to_string(), 9),
+            ("b".to_string(), 1),
+            ("b".to_string(), 2),
+        ];
+        assert_eq!(keys.unwrap(), expected);
+    }
+
+    #[test]
+    fn prefix_works() {
+        let mut store = MockStorage::new();
+        let mut allowances = keyed_bucket::<_, (&str, &str), u64>(b"allowance", &mut store);
+
+        allowances.save(("owner", "spender"), &100).unwrap();
+        allowances.save(("owner", "other"), &50).unwrap();
+        allowances.save(("ownerother", ""), &7).unwrap();
+
+        // writes through the prefix end up in the full key
+        let mut owner = allowances.prefix("owner");
+        assert_eq!(owner.load("spender").unwrap(), 100);
+        owner.save("third", &25).unwrap();
+        assert_eq!(allowances.load(("owner", "third")).unwrap(), 25);
+
+        let reader = keyed_bucket_read::<_, (&str, &str), u64>(b"allowance", &store);
+        let owner = reader.prefix("owner");
+        assert_eq!(owner.load("other").unwrap(), 50);
+        assert_eq!(owner.may_load("").unwrap(), None);
+        assert_eq!(reader.prefix("ownerother").load("").unwrap(), 7);
+    }
+
+    #[test]
+    fn oversized_key_components_are_rejected() {
+        let mut store = MockStorage::new();
+        let mut allowances = keyed_bucket::<_, (&[u8], &str), u64>(b"allowance", &mut store);
+        let long = vec![b'x'; 0x10000];
+        let long = long.as_slice();
+
+        let err = allowances.save((long, "spender"), &100).unwrap_err();
+        assert_eq!(err, StorageError::NamespaceTooLong { length: 0x10000 });
+        assert!(allowances.may_load((long, "spender")).is_err());
+        assert!(allowances.try_prefix(long).is_err());
+        // nothing was stored, so removing is a no-op
+        allowances.remove((long, "spender")).unwrap();
+
+        // the last component is not length-prefixed
+        allowances.save((&b"owner"[..], "spender"), &100).unwrap();
+        let reader = keyed_bucket_read::<_, (&[u8], &str), u64>(b"allowance", &store);
+        assert_eq!(
+            reader
+                .try_prefix(&b"owner"[..])
+                .unwrap()
+                .load("spender")
+                .unwrap(),
+            100
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_over_prefix() {
+        let mut store = MockStorage::new();
+        let mut balances = keyed_bucket::<_, (&str, &str, u32), u64>(b"balance", &mut store);
+
+        balances.save(("atom", "alice", 1), &100).unwrap();
+        balances.save(("atom", "bob", 2), &200).unwrap();
+        balances.save(("atom", "bob", 1), &300).unwrap();
+        balances.save(("atomic", "carl", 1), &400).unwrap();
+        balances.save(("btc", "alice", 1), &500).unwrap();
+
+        let reader = keyed_bucket_read::<_, (&str, &str, u32), u64>(b"balance", &store);
+
+        // all entries for one denom. Note that length-prefixed components sort by length first
+        let atom: Result<Vec<((String, u32), u64)>> = reader
+            .prefix("atom")
+            .range(None, None, Order::Ascending)
+            .collect();
+        let expected = vec![
+            (("bob".to_string(), 1), 300),
+            (("bob".to_string(), 2), 200),
+            (("alice".to_string(), 1), 100),
+        ];
+        assert_eq!(atom.unwrap(), expected);
+
+        // prefixes can be chained
+        let bob: Result<Vec<(u32, u64)>> = reader
+            .prefix("atom")
+            .prefix("bob")
+            .range(None, None, Order::Descending)
+            .collect();
+        assert_eq!(bob.unwrap(), vec![(2, 200), (1, 300)]);
+    }
+}
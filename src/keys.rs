@@ -0,0 +1,316 @@
+use std::mem::size_of;
+
+use crate::errors::{InvalidKey, Result};
+use crate::namespace_helpers::{split_first_key, try_key_prefix, try_key_prefix_nested};
+
+/// PrimaryKey converts typed keys into the raw bytes stored in the database and back.
+///
+/// The encoding preserves ordering, so iterating over raw keys returns the typed keys
+/// in their natural order. Output is the type we get back when parsing a raw key, which allows
+/// borrowed keys like `&str` to be used for lookups and come back owned (`String`) from `range`.
+pub trait PrimaryKey {
+    type Output;
+
+    /// joined_key returns the raw bytes used as key in storage. Returns an error if the key
+    /// cannot be encoded, e.g. if a component of a composite key is longer than 0xFFFF bytes
+    fn joined_key(&self) -> Result<Vec<u8>>;
+
+    /// parse_key is the inverse of joined_key, returning an error if the bytes are not a valid key
+    fn parse_key(raw: &[u8]) -> Result<Self::Output>;
+}
+
+impl PrimaryKey for Vec<u8> {
+    type Output = Vec<u8>;
+
+    fn joined_key(&self) -> Result<Vec<u8>> {
+        Ok(self.clone())
+    }
+
+    fn parse_key(raw: &[u8]) -> Result<Vec<u8>> {
+        Ok(raw.to_vec())
+    }
+}
+
+impl PrimaryKey for &[u8] {
+    type Output = Vec<u8>;
+
+    fn joined_key(&self) -> Result<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+
+    fn parse_key(raw: &[u8]) -> Result<Vec<u8>> {
+        Ok(raw.to_vec())
+    }
+}
+
+impl PrimaryKey for String {
+    type Output = String;
+
+    fn joined_key(&self) -> Result<Vec<u8>> {
+        Ok(self.as_bytes().to_vec())
+    }
+
+    fn parse_key(raw: &[u8]) -> Result<String> {
+        parse_string(raw)
+    }
+}
+
+impl PrimaryKey for &str {
+    type Output = String;
+
+    fn joined_key(&self) -> Result<Vec<u8>> {
+        Ok(self.as_bytes().to_vec())
+    }
+
+    fn parse_key(raw: &[u8]) -> Result<String> {
+        parse_string(raw)
+    }
+}
+
+fn parse_string(raw: &[u8]) -> Result<String> {
+    match String::from_utf8(raw.to_vec()) {
+        Ok(s) => Ok(s),
+        Err(_) => InvalidKey {
+            msg: "key is not valid utf-8",
+        }
+        .fail(),
+    }
+}
+
+// Unsigned integers are stored big endian, so byte order matches numeric order
+macro_rules! unsigned_key {
+    ($($t:ty),*) => {
+        $(
+            impl PrimaryKey for $t {
+                type Output = $t;
+
+                fn joined_key(&self) -> Result<Vec<u8>> {
+                    Ok(self.to_be_bytes().to_vec())
+                }
+
+                fn parse_key(raw: &[u8]) -> Result<$t> {
+                    let mut buf = [0u8; size_of::<$t>()];
+                    if raw.len() != buf.len() {
+                        return InvalidKey {
+                            msg: "invalid length for integer key",
+                        }
+                        .fail();
+                    }
+                    buf.copy_from_slice(raw);
+                    Ok(<$t>::from_be_bytes(buf))
+                }
+            }
+        )*
+    };
+}
+
+unsigned_key!(u8, u16, u32, u64, u128);
+
+// Signed integers flip the sign bit before being stored big endian, so negative numbers
+// sort before positive ones
+macro_rules! signed_key {
+    ($($t:ty => $u:ty),*) => {
+        $(
+            impl PrimaryKey for $t {
+                type Output = $t;
+
+                fn joined_key(&self) -> Result<Vec<u8>> {
+                    let flipped = (*self as $u) ^ (1 << (size_of::<$u>() * 8 - 1));
+                    Ok(flipped.to_be_bytes().to_vec())
+                }
+
+                fn parse_key(raw: &[u8]) -> Result<$t> {
+                    let flipped = <$u>::parse_key(raw)?;
+                    Ok((flipped ^ (1 << (size_of::<$u>() * 8 - 1))) as $t)
+                }
+            }
+        )*
+    };
+}
+
+signed_key!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+/// CompositeKey is implemented by keys that consist of a first component (Prefix) and the rest
+/// of the key (Suffix). All keys sharing the same Prefix can be accessed as their own KeyedBucket,
+/// which uses the Suffix as keys (see KeyedBucket::prefix).
+pub trait CompositeKey: PrimaryKey {
+    type Prefix: PrimaryKey;
+    type Suffix: PrimaryKey;
+}
+
+// joined_prefix returns the raw bytes shared by all composite keys starting with the given prefix
+pub(crate) fn joined_prefix<P: PrimaryKey>(prefix: &P) -> Result<Vec<u8>> {
+    try_key_prefix(&prefix.joined_key()?)
+}
+
+/// Composite keys length-prefix all but the last component like a namespace (see key_prefix_nested),
+/// so (b"ab", b"c") and (b"a", b"bc") never collide. As a consequence, iteration orders
+/// variable-length components by length first and only then by content
+impl<A: PrimaryKey, B: PrimaryKey> PrimaryKey for (A, B) {
+    type Output = (A::Output, B::Output);
+
+    fn joined_key(&self) -> Result<Vec<u8>> {
+        let mut out = joined_prefix(&self.0)?;
+        out.extend_from_slice(&self.1.joined_key()?);
+        Ok(out)
+    }
+
+    fn parse_key(raw: &[u8]) -> Result<Self::Output> {
+        let (a, rest) = split_first_key(raw)?;
+        Ok((A::parse_key(a)?, B::parse_key(rest)?))
+    }
+}
+
+impl<A: PrimaryKey, B: PrimaryKey> CompositeKey for (A, B) {
+    type Prefix = A;
+    type Suffix = B;
+}
+
+// (a, b, c) is stored as key_prefix_nested(&[a, b]) + c, which is the same as (a, (b, c)).
+// This way a prefix on the first component yields (B, C) keys, which can be prefixed again.
+impl<A: PrimaryKey, B: PrimaryKey, C: PrimaryKey> PrimaryKey for (A, B, C) {
+    type Output = (A::Output, B::Output, C::Output);
+
+    fn joined_key(&self) -> Result<Vec<u8>> {
+        let mut out = try_key_prefix_nested(&[&self.0.joined_key()?, &self.1.joined_key()?])?;
+        out.extend_from_slice(&self.2.joined_key()?);
+        Ok(out)
+    }
+
+    fn parse_key(raw: &[u8]) -> Result<Self::Output> {
+        let (a, rest) = split_first_key(raw)?;
+        let (b, c) = <(B, C)>::parse_key(rest)?;
+        Ok((A::parse_key(a)?, b, c))
+    }
+}
+
+impl<A: PrimaryKey, B: PrimaryKey, C: PrimaryKey> CompositeKey for (A, B, C) {
+    type Prefix = A;
+    type Suffix = (B, C);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::StorageError;
+    use crate::namespace_helpers::key_prefix_nested;
+
+    #[test]
+    fn bytes_and_strings() {
+        let k = b"hello".to_vec();
+        assert_eq!(k.joined_key().unwrap(), b"hello".to_vec());
+        assert_eq!(Vec::<u8>::parse_key(b"hello").unwrap(), k);
+
+        let k: &[u8] = b"hello";
+        assert_eq!(k.joined_key().unwrap(), b"hello".to_vec());
+
+        let k = "hello".to_string();
+        assert_eq!(k.joined_key().unwrap(), b"hello".to_vec());
+        assert_eq!(String::parse_key(b"hello").unwrap(), k);
+        assert_eq!("hello".joined_key().unwrap(), b"hello".to_vec());
+        assert_eq!(<&str>::parse_key(b"hello").unwrap(), k);
+
+        assert!(String::parse_key(b"\xff\xfe").is_err());
+    }
+
+    #[test]
+    fn unsigned_integers() {
+        assert_eq!(5u8.joined_key().unwrap(), vec![5]);
+        assert_eq!(0x1234u16.joined_key().unwrap(), vec![0x12, 0x34]);
+        assert_eq!(1u64.joined_key().unwrap(), vec![0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(
+            u128::parse_key(&(12345u128).joined_key().unwrap()).unwrap(),
+            12345
+        );
+        assert_eq!(
+            u32::parse_key(&std::u32::MAX.joined_key().unwrap()).unwrap(),
+            std::u32::MAX
+        );
+
+        // length must match exactly
+        assert!(u32::parse_key(&[0, 1]).is_err());
+        assert!(u16::parse_key(&[0, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn signed_integers() {
+        assert_eq!((-1i8).joined_key().unwrap(), vec![0x7f]);
+        assert_eq!(0i8.joined_key().unwrap(), vec![0x80]);
+        assert_eq!(1i16.joined_key().unwrap(), vec![0x80, 0x01]);
+
+        for &v in &[std::i64::MIN, -300, -1, 0, 1, 300, std::i64::MAX] {
+            assert_eq!(i64::parse_key(&v.joined_key().unwrap()).unwrap(), v);
+        }
+        assert_eq!(
+            i128::parse_key(&(-7i128).joined_key().unwrap()).unwrap(),
+            -7
+        );
+    }
+
+    #[test]
+    fn integer_keys_preserve_order() {
+        let values = [std::i32::MIN, -1000, -1, 0, 1, 255, 256, std::i32::MAX];
+        let keys: Vec<Vec<u8>> = values.iter().map(|v| v.joined_key().unwrap()).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+
+        let values = [0u64, 1, 255, 256, 65536, std::u64::MAX];
+        let keys: Vec<Vec<u8>> = values.iter().map(|v| v.joined_key().unwrap()).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
+
+    #[test]
+    fn tuple_keys() {
+        let k = ("ab", 1u16);
+        assert_eq!(k.joined_key().unwrap(), b"\x00\x02ab\x00\x01".to_vec());
+        assert_eq!(
+            <(&str, u16)>::parse_key(&k.joined_key().unwrap()).unwrap(),
+            ("ab".to_string(), 1)
+        );
+
+        // no collisions when moving bytes between the components
+        let k1: (&[u8], &[u8]) = (b"ab", b"c");
+        let k2: (&[u8], &[u8]) = (b"a", b"bc");
+        assert_ne!(k1.joined_key().unwrap(), k2.joined_key().unwrap());
+
+        // invalid length prefix
+        assert!(<(String, String)>::parse_key(b"\x00\x05ab").is_err());
+        assert!(<(String, String)>::parse_key(b"\x00").is_err());
+
+        // components that don't fit the length prefix are rejected instead of panicking
+        let long = vec![b'a'; 0x10000];
+        match (long.as_slice(), 1u8).joined_key().unwrap_err() {
+            StorageError::NamespaceTooLong { length } => assert_eq!(length, 0x10000),
+            e => panic!("Unexpected error {}", e),
+        }
+        assert!(("a", long.as_slice(), 1u8).joined_key().is_err());
+        // the last component is not length-prefixed
+        assert!(("a", long.as_slice()).joined_key().is_ok());
+    }
+
+    #[test]
+    fn triple_keys() {
+        let k = ("a", "bc", 7u8);
+        assert_eq!(
+            k.joined_key().unwrap(),
+            [key_prefix_nested(&[b"a", b"bc"]), vec![7]].concat()
+        );
+        // same encoding as nesting the tuples on the right side
+        assert_eq!(
+            k.joined_key().unwrap(),
+            ("a", ("bc", 7u8)).joined_key().unwrap()
+        );
+        assert_eq!(
+            <(&str, &str, u8)>::parse_key(&k.joined_key().unwrap()).unwrap(),
+            ("a".to_string(), "bc".to_string(), 7)
+        );
+
+        // a prefix is shared by all keys starting with it
+        let prefix = joined_prefix(&"a").unwrap();
+        assert!(k.joined_key().unwrap().starts_with(&prefix));
+        assert!(!("ab", "c", 7u8).joined_key().unwrap().starts_with(&prefix));
+    }
+}
@@ -1,14 +1,76 @@
+mod auto_increment;
 mod bucket;
+mod cache;
+mod codec;
+mod deque;
+mod errors;
+mod expiring_bucket;
+mod indexed_bucket;
+mod keyed_bucket;
+mod keys;
+mod metered;
 mod namespace_helpers;
+#[cfg(feature = "iterator")]
+mod pagination;
 mod prefix;
 mod sequence;
 mod singleton;
+#[cfg(feature = "iterator")]
+mod snapshot;
+#[cfg(feature = "iterator")]
+mod snapshot_singleton;
+mod transaction;
 mod type_helpers;
 mod typed;
+mod versioned;
+mod versioned_singleton;
 
+pub use auto_increment::{
+    auto_increment_bucket, auto_increment_bucket_read, AutoIncrementBucket,
+    ReadonlyAutoIncrementBucket,
+};
 pub use bucket::{bucket, bucket_read, Bucket, ReadonlyBucket};
+pub use cache::{CachedStorage, ReadonlyCachedStorage, TypedCache};
+#[cfg(feature = "bincode")]
+pub use codec::{Bincode, CompactBincode};
+pub use codec::{Codec, Json};
+pub use deque::{deque, deque_read, Deque, ReadonlyDeque};
+pub use errors::{Result, StorageError};
+pub use expiring_bucket::{
+    expiring_bucket, expiring_bucket_read, BlockTime, Expiration, ExpiringBucket,
+    ReadonlyExpiringBucket,
+};
+#[cfg(feature = "iterator")]
+pub use indexed_bucket::IndexReader;
+pub use indexed_bucket::{
+    indexed_bucket, indexed_bucket_read, Index, IndexedBucket, ReadonlyIndexedBucket,
+};
+pub use keyed_bucket::{keyed_bucket, keyed_bucket_read, KeyedBucket, ReadonlyKeyedBucket};
+pub use keys::{CompositeKey, PrimaryKey};
+pub use metered::{MeteredStorage, ReadonlyMeteredStorage, StorageReport, StorageStats};
+pub use namespace_helpers::{decode_key, DecodedKey};
+#[cfg(feature = "iterator")]
+pub use pagination::{Page, Pagination};
 pub use prefix::{prefixed, prefixed_read, PrefixedStorage, ReadonlyPrefixedStorage};
-pub use sequence::{currval, nextval, sequence};
+pub use sequence::{
+    currval, nextval, nextval_by, reset, sequence, sequence_read, typed_sequence,
+    typed_sequence_read, SequenceSource, SequenceValue,
+};
 pub use singleton::{singleton, singleton_read, ReadonlySingleton, Singleton};
+#[cfg(feature = "iterator")]
+pub use snapshot::{
+    snapshot_bucket, snapshot_bucket_read, ReadonlySnapshotBucket, SnapshotBucket, Strategy,
+};
+#[cfg(feature = "iterator")]
+pub use snapshot_singleton::{
+    snapshot_singleton, snapshot_singleton_read, ReadonlySnapshotSingleton, SnapshotSingleton,
+};
+pub use transaction::{transactional, StorageTransaction};
 pub use type_helpers::{deserialize, serialize};
 pub use typed::{typed, typed_read, ReadonlyTypedStorage, TypedStorage};
+pub use versioned::{
+    versioned_bucket, versioned_bucket_read, Migrate, ReadonlyVersionedBucket, VersionedBucket,
+};
+pub use versioned_singleton::{
+    versioned_singleton, versioned_singleton_read, ReadonlyVersionedSingleton, VersionedSingleton,
+};
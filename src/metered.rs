@@ -0,0 +1,371 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+
+#[cfg(feature = "iterator")]
+use cosmwasm_std::{Order, KV};
+use cosmwasm_std::{ReadonlyStorage, StdResult, Storage};
+
+use crate::namespace_helpers::split_namespaces;
+
+/// StorageStats counts the storage accesses of one namespace (or all of them, see total).
+/// Every item returned by range counts as one read. Bytes only count the values, not the keys
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StorageStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub removes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+impl AddAssign for StorageStats {
+    fn add_assign(&mut self, other: Self) {
+        self.reads += other.reads;
+        self.writes += other.writes;
+        self.removes += other.removes;
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+    }
+}
+
+/// StorageReport maps the decoded namespaces (outermost first) to their stats.
+/// Keys without a length-prefixed namespace are reported under an empty list
+pub type StorageReport = BTreeMap<Vec<Vec<u8>>, StorageStats>;
+
+// Meter collects the stats of a MeteredStorage or ReadonlyMeteredStorage
+struct Meter {
+    depth: usize,
+    stats: RefCell<StorageReport>,
+}
+
+impl Meter {
+    fn new(depth: usize) -> Self {
+        Meter {
+            depth,
+            stats: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    fn record<F: FnOnce(&mut StorageStats)>(&self, key: &[u8], update: F) {
+        let (namespaces, _) = split_namespaces(key, self.depth);
+        let namespace = namespaces.into_iter().map(|ns| ns.to_vec()).collect();
+        update(self.stats.borrow_mut().entry(namespace).or_default());
+    }
+
+    fn read(&self, key: &[u8], value: &Option<Vec<u8>>) {
+        self.record(key, |stats| {
+            stats.reads += 1;
+            stats.bytes_read += value.as_ref().map_or(0, |v| v.len() as u64);
+        });
+    }
+
+    fn stats(&self, namespace: &[&[u8]]) -> StorageStats {
+        let namespace: Vec<Vec<u8>> = namespace.iter().map(|ns| ns.to_vec()).collect();
+        self.stats
+            .borrow()
+            .get(&namespace)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn total(&self) -> StorageStats {
+        let mut total = StorageStats::default();
+        for stats in self.stats.borrow().values() {
+            total += *stats;
+        }
+        total
+    }
+}
+
+/// MeteredStorage counts all reads, writes and removes passing through it, along with the bytes
+/// read and written, grouped by the namespace of the key. This allows to find out which buckets
+/// dominate the gas cost of a contract, or to assert on storage costs in tests.
+///
+/// By default, keys are grouped by their outermost namespace, so e.g. the indexes and nested
+/// buckets of a namespace are counted together. Use with_depth to report nested namespaces
+/// separately. Keys are never decoded beyond that depth, as binary keys (like big endian
+/// integers) may look like length-prefixed namespaces.
+pub struct MeteredStorage<'a, S: Storage> {
+    storage: &'a mut S,
+    meter: Meter,
+}
+
+impl<'a, S: Storage> MeteredStorage<'a, S> {
+    pub fn new(storage: &'a mut S) -> Self {
+        Self::with_depth(storage, 1)
+    }
+
+    /// with_depth groups keys by up to depth nested namespaces
+    pub fn with_depth(storage: &'a mut S, depth: usize) -> Self {
+        MeteredStorage {
+            storage,
+            meter: Meter::new(depth),
+        }
+    }
+
+    /// report returns the stats of every namespace accessed so far
+    pub fn report(&self) -> StorageReport {
+        self.meter.stats.borrow().clone()
+    }
+
+    /// stats returns the stats of the given (possibly nested) namespace.
+    /// Use an empty slice for keys without namespace
+    pub fn stats(&self, namespace: &[&[u8]]) -> StorageStats {
+        self.meter.stats(namespace)
+    }
+
+    /// total sums up the stats of all namespaces
+    pub fn total(&self) -> StorageStats {
+        self.meter.total()
+    }
+
+    /// reset clears all stats collected so far
+    pub fn reset(&mut self) {
+        self.meter.stats.get_mut().clear();
+    }
+}
+
+impl<'a, S: Storage> ReadonlyStorage for MeteredStorage<'a, S> {
+    fn get(&self, key: &[u8]) -> StdResult<Option<Vec<u8>>> {
+        let value = self.storage.get(key)?;
+        self.meter.read(key, &value);
+        Ok(value)
+    }
+
+    #[cfg(feature = "iterator")]
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> StdResult<Box<dyn Iterator<Item = StdResult<KV>> + 'b>> {
+        let meter = &self.meter;
+        let mapped = self.storage.range(start, end, order)?.map(move |item| {
+            item.map(|(k, v)| {
+                meter.record(&k, |stats| {
+                    stats.reads += 1;
+                    stats.bytes_read += v.len() as u64;
+                });
+                (k, v)
+            })
+        });
+        Ok(Box::new(mapped))
+    }
+}
+
+impl<'a, S: Storage> Storage for MeteredStorage<'a, S> {
+    fn set(&mut self, key: &[u8], value: &[u8]) -> StdResult<()> {
+        self.meter.record(key, |stats| {
+            stats.writes += 1;
+            stats.bytes_written += value.len() as u64;
+        });
+        self.storage.set(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> StdResult<()> {
+        self.meter.record(key, |stats| stats.removes += 1);
+        self.storage.remove(key)
+    }
+}
+
+/// ReadonlyMeteredStorage counts the reads of a ReadonlyStorage, e.g. in queries.
+/// See MeteredStorage
+pub struct ReadonlyMeteredStorage<'a, S: ReadonlyStorage> {
+    storage: &'a S,
+    meter: Meter,
+}
+
+impl<'a, S: ReadonlyStorage> ReadonlyMeteredStorage<'a, S> {
+    pub fn new(storage: &'a S) -> Self {
+        Self::with_depth(storage, 1)
+    }
+
+    /// with_depth groups keys by up to depth nested namespaces
+    pub fn with_depth(storage: &'a S, depth: usize) -> Self {
+        ReadonlyMeteredStorage {
+            storage,
+            meter: Meter::new(depth),
+        }
+    }
+
+    /// report returns the stats of every namespace accessed so far
+    pub fn report(&self) -> StorageReport {
+        self.meter.stats.borrow().clone()
+    }
+
+    /// stats returns the stats of the given (possibly nested) namespace.
+    /// Use an empty slice for keys without namespace
+    pub fn stats(&self, namespace: &[&[u8]]) -> StorageStats {
+        self.meter.stats(namespace)
+    }
+
+    /// total sums up the stats of all namespaces
+    pub fn total(&self) -> StorageStats {
+        self.meter.total()
+    }
+
+    /// reset clears all stats collected so far
+    pub fn reset(&mut self) {
+        self.meter.stats.get_mut().clear();
+    }
+}
+
+impl<'a, S: ReadonlyStorage> ReadonlyStorage for ReadonlyMeteredStorage<'a, S> {
+    fn get(&self, key: &[u8]) -> StdResult<Option<Vec<u8>>> {
+        let value = self.storage.get(key)?;
+        self.meter.read(key, &value);
+        Ok(value)
+    }
+
+    #[cfg(feature = "iterator")]
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> StdResult<Box<dyn Iterator<Item = StdResult<KV>> + 'b>> {
+        let meter = &self.meter;
+        let mapped = self.storage.range(start, end, order)?.map(move |item| {
+            item.map(|(k, v)| {
+                meter.record(&k, |stats| {
+                    stats.reads += 1;
+                    stats.bytes_read += v.len() as u64;
+                });
+                (k, v)
+            })
+        });
+        Ok(Box::new(mapped))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+
+    use crate::{bucket, bucket_read, singleton, Bucket};
+
+    #[test]
+    fn counts_by_namespace() {
+        let mut store = MockStorage::new();
+        let mut metered = MeteredStorage::new(&mut store);
+
+        bucket::<_, u64>(b"balances", &mut metered)
+            .save(b"alice", &1234)
+            .unwrap();
+        bucket::<_, u64>(b"balances", &mut metered)
+            .save(b"bob", &5)
+            .unwrap();
+        singleton::<_, String>(&mut metered, b"owner")
+            .save(&"admin".to_string())
+            .unwrap();
+        metered.set(b"raw", b"data").unwrap();
+
+        let balances = bucket_read::<_, u64>(b"balances", &metered);
+        assert_eq!(balances.load(b"alice").unwrap(), 1234);
+        assert_eq!(balances.may_load(b"carl").unwrap(), None);
+        bucket::<_, u64>(b"balances", &mut metered)
+            .remove(b"bob")
+            .unwrap();
+
+        assert_eq!(
+            metered.stats(&[b"balances"]),
+            StorageStats {
+                reads: 2,
+                writes: 2,
+                removes: 1,
+                bytes_read: 4,
+                bytes_written: 5,
+            }
+        );
+        assert_eq!(
+            metered.stats(&[b"owner"]),
+            StorageStats {
+                writes: 1,
+                bytes_written: 7,
+                ..StorageStats::default()
+            }
+        );
+        assert_eq!(metered.stats(&[]).bytes_written, 4);
+        assert_eq!(metered.stats(&[b"unknown"]), StorageStats::default());
+        assert_eq!(metered.report().len(), 3);
+
+        let total = metered.total();
+        assert_eq!(total.writes, 4);
+        assert_eq!(total.bytes_written, 16);
+
+        metered.reset();
+        assert_eq!(metered.total(), StorageStats::default());
+        assert!(metered.report().is_empty());
+    }
+
+    #[test]
+    fn nested_namespaces() {
+        let mut store = MockStorage::new();
+        let mut metered = MeteredStorage::with_depth(&mut store, 2);
+        Bucket::<_, u32>::multilevel(&[b"allowance", b"alice"], &mut metered)
+            .save(b"bob", &7)
+            .unwrap();
+        // keys are not decoded beyond the depth
+        Bucket::<_, u32>::multilevel(&[b"allowance", b"carl"], &mut metered)
+            .save(b"\x00\x00", &8)
+            .unwrap();
+        // but within the depth, binary keys may look like a namespace
+        bucket::<_, u32>(b"config", &mut metered)
+            .save(b"\x00\x01a", &9)
+            .unwrap();
+
+        let report = metered.report();
+        let namespaces: Vec<Vec<Vec<u8>>> = report.keys().cloned().collect();
+        assert_eq!(
+            namespaces,
+            vec![
+                vec![b"allowance".to_vec(), b"alice".to_vec()],
+                vec![b"allowance".to_vec(), b"carl".to_vec()],
+                vec![b"config".to_vec(), b"a".to_vec()],
+            ]
+        );
+        assert_eq!(metered.stats(&[b"allowance", b"alice"]).writes, 1);
+    }
+
+    #[test]
+    fn readonly_counts_reads() {
+        let mut store = MockStorage::new();
+        bucket::<_, u64>(b"balances", &mut store)
+            .save(b"alice", &1234)
+            .unwrap();
+
+        let mut metered = ReadonlyMeteredStorage::new(&store);
+        let balances = bucket_read::<_, u64>(b"balances", &metered);
+        balances.load(b"alice").unwrap();
+        balances.load(b"alice").unwrap();
+        assert_eq!(
+            metered.stats(&[b"balances"]),
+            StorageStats {
+                reads: 2,
+                bytes_read: 8,
+                ..StorageStats::default()
+            }
+        );
+        metered.reset();
+        assert_eq!(metered.total().reads, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_counts_items() {
+        let mut store = MockStorage::new();
+        let mut numbers = bucket::<_, u32>(b"numbers", &mut store);
+        for i in 0..4u32 {
+            numbers.save(&[i as u8], &(i * 10)).unwrap();
+        }
+
+        let metered = ReadonlyMeteredStorage::new(&store);
+        let reader = bucket_read::<_, u32>(b"numbers", &metered);
+        // only the consumed items are counted
+        let first: Vec<_> = reader.range(None, None, Order::Ascending).take(3).collect();
+        assert_eq!(first.len(), 3);
+        assert_eq!(metered.stats(&[b"numbers"]).reads, 3);
+    }
+}
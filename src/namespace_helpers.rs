@@ -1,10 +1,16 @@
-use cosmwasm::traits::{ReadonlyStorage, Storage};
+use std::fmt;
+
+#[cfg(feature = "iterator")]
+use cosmwasm_std::{Order, KV};
+use cosmwasm_std::{ReadonlyStorage, StdResult, Storage};
+
+use crate::errors::{InvalidKey, NamespaceTooLong, Result};
 
 pub(crate) fn get_with_prefix<S: ReadonlyStorage>(
     storage: &S,
     namespace: &[u8],
     key: &[u8],
-) -> Option<Vec<u8>> {
+) -> StdResult<Option<Vec<u8>>> {
     let mut k = namespace.to_vec();
     k.extend_from_slice(key);
     storage.get(&k)
@@ -15,55 +21,224 @@ pub(crate) fn set_with_prefix<S: Storage>(
     namespace: &[u8],
     key: &[u8],
     value: &[u8],
-) {
+) -> StdResult<()> {
     let mut k = namespace.to_vec();
     k.extend_from_slice(key);
     storage.set(&k, value)
 }
 
+pub(crate) fn remove_with_prefix<S: Storage>(
+    storage: &mut S,
+    namespace: &[u8],
+    key: &[u8],
+) -> StdResult<()> {
+    let mut k = namespace.to_vec();
+    k.extend_from_slice(key);
+    storage.remove(&k)
+}
+
+/// range_with_prefix iterates over all keys in the given namespace that lie in [start, end).
+/// The namespace is stripped from the returned keys, so start and end as well as the results
+/// are relative to the namespace. If the storage fails to start the range, the error is returned
+/// as the only item.
+#[cfg(feature = "iterator")]
+pub(crate) fn range_with_prefix<'a, S: ReadonlyStorage>(
+    storage: &'a S,
+    namespace: &[u8],
+    start: Option<&[u8]>,
+    end: Option<&[u8]>,
+    order: Order,
+) -> Box<dyn Iterator<Item = StdResult<KV>> + 'a> {
+    // prepare start, end with prefix
+    let start = match start {
+        Some(s) => concat(namespace, s),
+        None => namespace.to_vec(),
+    };
+    let end = match end {
+        Some(e) => Some(concat(namespace, e)),
+        None => namespace_upper_bound(namespace),
+    };
+
+    let base = match end {
+        Some(ref e) => storage.range(Some(&start), Some(e), order),
+        None => storage.range(Some(&start), None, order),
+    };
+    let base = match base {
+        Ok(iter) => iter,
+        Err(e) => return Box::new(std::iter::once(Err(e))),
+    };
+
+    // make a copy for the closure to handle lifetimes safely
+    let prefix_len = namespace.len();
+    let mapped = base.map(move |item| item.map(|(k, v)| (k[prefix_len..].to_vec(), v)));
+    Box::new(mapped)
+}
+
+#[cfg(feature = "iterator")]
+fn concat(namespace: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut k = namespace.to_vec();
+    k.extend_from_slice(key);
+    k
+}
+
+// namespace_upper_bound returns the smallest key that is larger than all keys in the namespace,
+// or None if there is no such key (e.g. the namespace is empty or only consists of 0xFF bytes)
+#[cfg(feature = "iterator")]
+fn namespace_upper_bound(namespace: &[u8]) -> Option<Vec<u8>> {
+    let mut out = namespace.to_vec();
+    while let Some(last) = out.pop() {
+        if last < 0xFF {
+            out.push(last + 1);
+            return Some(out);
+        }
+    }
+    None
+}
+
 // Calculates the raw key prefix for a given namespace
 // as documented in https://github.com/webmaster128/key-namespacing#length-prefixed-keys
+//
+// Panics if the namespace is longer than 0xFFFF bytes, use try_key_prefix for untrusted input
 pub(crate) fn key_prefix(namespace: &[u8]) -> Vec<u8> {
-    let mut out = Vec::with_capacity(namespace.len() + 2);
-    extend_with_prefix(&mut out, namespace);
-    out
+    try_key_prefix(namespace).unwrap_or_else(|e| panic!("{}", e))
 }
 
 // Calculates the raw key prefix for a given nested namespace
 // as documented in https://github.com/webmaster128/key-namespacing#nesting
+//
+// Panics if a namespace is longer than 0xFFFF bytes, use try_key_prefix_nested for untrusted input
 pub(crate) fn key_prefix_nested(namespaces: &[&[u8]]) -> Vec<u8> {
+    try_key_prefix_nested(namespaces).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// try_key_prefix works like key_prefix, but returns NamespaceTooLong instead of panicking
+pub(crate) fn try_key_prefix(namespace: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(namespace.len() + 2);
+    extend_with_prefix(&mut out, namespace)?;
+    Ok(out)
+}
+
+// try_key_prefix_nested works like key_prefix_nested, but returns NamespaceTooLong instead of panicking
+pub(crate) fn try_key_prefix_nested(namespaces: &[&[u8]]) -> Result<Vec<u8>> {
     let mut size = namespaces.len();
     for &namespace in namespaces {
         size += namespace.len() + 2;
     }
-
     let mut out = Vec::with_capacity(size);
     for &namespace in namespaces {
-        extend_with_prefix(&mut out, namespace);
+        extend_with_prefix(&mut out, namespace)?;
+    }
+    Ok(out)
+}
+
+// split_first_key is the inverse of key_prefix. It reads one length-prefixed namespace from the
+// start of the key and returns it along with the remaining bytes
+pub(crate) fn split_first_key(key: &[u8]) -> Result<(&[u8], &[u8])> {
+    if key.len() < 2 {
+        return InvalidKey {
+            msg: "key too short to contain a length prefix",
+        }
+        .fail();
+    }
+    let len = ((key[0] as usize) << 8) | key[1] as usize;
+    if key.len() < len + 2 {
+        return InvalidKey {
+            msg: "key shorter than its length prefix",
+        }
+        .fail();
+    }
+    Ok((&key[2..len + 2], &key[len + 2..]))
+}
+
+/// DecodedKey is a raw storage key split into its length-prefixed namespaces and the
+/// remaining key, see decode_key. It displays as e.g. `people/2020: maria`, with non-printable
+/// parts shown as 0x prefixed hex
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecodedKey {
+    pub namespaces: Vec<Vec<u8>>,
+    pub key: Vec<u8>,
+}
+
+impl fmt::Display for DecodedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let namespaces: Vec<String> = self.namespaces.iter().map(|ns| printable_key(ns)).collect();
+        match (namespaces.is_empty(), self.key.is_empty()) {
+            (true, _) => write!(f, "{}", printable_key(&self.key)),
+            (false, true) => write!(f, "{}", namespaces.join("/")),
+            (false, false) => write!(f, "{}: {}", namespaces.join("/"), printable_key(&self.key)),
+        }
     }
-    out
+}
+
+/// decode_key is the inverse of key_prefix and key_prefix_nested, meant for inspecting raw state.
+/// It splits all length-prefixed namespaces from the start of a storage key and returns them
+/// along with the remaining key.
+///
+/// Storage doesn't mark where the namespaces end, so a remaining key that happens to start with
+/// a valid length prefix (like the big endian integer 1, b"\x00\x00\x00\x01") is decoded as
+/// further namespaces.
+pub fn decode_key(raw: &[u8]) -> DecodedKey {
+    let (namespaces, key) = split_namespaces(raw, std::usize::MAX);
+    DecodedKey {
+        namespaces: namespaces.into_iter().map(|ns| ns.to_vec()).collect(),
+        key: key.to_vec(),
+    }
+}
+
+// printable_key returns the key as string if it is readable utf-8, otherwise as 0x prefixed hex
+pub(crate) fn printable_key(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(s) if !s.chars().any(char::is_control) => s.to_string(),
+        _ => {
+            let hex: Vec<String> = key.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("0x{}", hex.concat())
+        }
+    }
+}
+
+// split_namespaces reads up to max_depth length-prefixed namespaces from the start of the key
+// and returns them along with the remaining bytes. It stops early at the first part that is not
+// a valid length prefix, so raw keys without namespace return no namespaces at all
+pub(crate) fn split_namespaces(key: &[u8], max_depth: usize) -> (Vec<&[u8]>, &[u8]) {
+    let mut namespaces = Vec::new();
+    let mut rest = key;
+    while namespaces.len() < max_depth {
+        match split_first_key(rest) {
+            Ok((namespace, remainder)) => {
+                namespaces.push(namespace);
+                rest = remainder;
+            }
+            Err(_) => break,
+        }
+    }
+    (namespaces, rest)
 }
 
 // extend_with_prefix is only for internal use to unify key_prefix and key_prefix_nested efficiently
 // as documented in https://github.com/webmaster128/key-namespacing#nesting
-fn extend_with_prefix(out: &mut Vec<u8>, namespace: &[u8]) {
-    out.extend_from_slice(&key_len(namespace));
+fn extend_with_prefix(out: &mut Vec<u8>, namespace: &[u8]) -> Result<()> {
+    out.extend_from_slice(&key_len(namespace)?);
     out.extend_from_slice(namespace);
+    Ok(())
 }
 
 // returns the length as a 2 byte big endian encoded integer
-fn key_len(prefix: &[u8]) -> [u8; 2] {
+fn key_len(prefix: &[u8]) -> Result<[u8; 2]> {
     if prefix.len() > 0xFFFF {
-        panic!("only supports namespaces up to length 0xFFFF")
+        return NamespaceTooLong {
+            length: prefix.len(),
+        }
+        .fail();
     }
     let length_bytes = (prefix.len() as u64).to_be_bytes();
-    [length_bytes[6], length_bytes[7]]
+    Ok([length_bytes[6], length_bytes[7]])
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use cosmwasm::mock::MockStorage;
+    use crate::errors::StorageError;
+    use cosmwasm_std::testing::MockStorage;
 
     #[test]
     fn key_prefix_works() {
@@ -99,6 +274,28 @@ mod test {
         key_prefix(&long_namespace);
     }
 
+    #[test]
+    fn try_key_prefix_rejects_too_long_prefix() {
+        let limit = 0xFFFF;
+        let max_namespace = vec![0; limit];
+        assert_eq!(
+            try_key_prefix(&max_namespace).unwrap(),
+            key_prefix(&max_namespace)
+        );
+
+        let long_namespace = vec![0; limit + 1];
+        match try_key_prefix(&long_namespace).unwrap_err() {
+            StorageError::NamespaceTooLong { length } => assert_eq!(length, limit + 1),
+            e => panic!("Unexpected error {}", e),
+        }
+        let nested: &[&[u8]] = &[b"foo", &long_namespace];
+        assert!(try_key_prefix_nested(nested).is_err());
+        assert_eq!(
+            try_key_prefix_nested(&[b"foo", b"bar"]).unwrap(),
+            key_prefix_nested(&[b"foo", b"bar"])
+        );
+    }
+
     #[test]
     fn key_prefix_nested_works() {
         assert_eq!(key_prefix_nested(&[]), b"");
@@ -113,19 +310,153 @@ mod test {
         );
     }
 
+    #[test]
+    fn split_first_key_works() {
+        assert_eq!(split_first_key(b"\x00\x00").unwrap(), (&b""[..], &b""[..]));
+        assert_eq!(
+            split_first_key(b"\x00\x03foobar").unwrap(),
+            (&b"foo"[..], &b"bar"[..])
+        );
+        let nested = key_prefix_nested(&[b"foo", b"bar"]);
+        assert_eq!(
+            split_first_key(&nested).unwrap(),
+            (&b"foo"[..], &b"\x00\x03bar"[..])
+        );
+
+        assert!(split_first_key(b"").is_err());
+        assert!(split_first_key(b"\x00").is_err());
+        assert!(split_first_key(b"\x00\x04foo").is_err());
+    }
+
+    #[test]
+    fn decode_key_round_trip() {
+        let namespaces: &[&[u8]] = &[b"foo", b"", b"\xff\x00"];
+        for depth in 0..=namespaces.len() {
+            let nested = &namespaces[..depth];
+            for key in &[&b""[..], b"bar", b"\xff\xff"] {
+                let raw = [key_prefix_nested(nested).as_slice(), key].concat();
+                let decoded = decode_key(&raw);
+                assert_eq!(decoded.namespaces, nested.to_vec());
+                assert_eq!(decoded.key, key.to_vec());
+            }
+        }
+        // a single namespace is the same as key_prefix
+        let decoded = decode_key(&key_prefix(b"config"));
+        assert_eq!(decoded.namespaces, vec![b"config".to_vec()]);
+        assert_eq!(decoded.key, b"".to_vec());
+        // a long namespace needs both bytes of the prefix
+        let long = vec![b'a'; 300];
+        assert_eq!(decode_key(&key_prefix(&long)).namespaces, vec![long]);
+
+        // keys looking like a length prefix are decoded as namespaces
+        let raw = [key_prefix(b"ids").as_slice(), b"\x00\x00\x00\x01"].concat();
+        let decoded = decode_key(&raw);
+        assert_eq!(decoded.namespaces, vec![b"ids".to_vec(), b"".to_vec()]);
+        assert_eq!(decoded.key, b"\x00\x01".to_vec());
+        // no namespace at all
+        assert_eq!(decode_key(b"raw").key, b"raw".to_vec());
+        assert_eq!(decode_key(b""), DecodedKey::default());
+    }
+
+    #[test]
+    fn decoded_key_display() {
+        let raw = [
+            key_prefix_nested(&[b"people", b"2020"]).as_slice(),
+            b"maria",
+        ]
+        .concat();
+        assert_eq!(decode_key(&raw).to_string(), "people/2020: maria");
+        assert_eq!(decode_key(&key_prefix(b"config")).to_string(), "config");
+        assert_eq!(decode_key(b"raw").to_string(), "raw");
+        let raw = [key_prefix(b"balances").as_slice(), b"\xfe\x01"].concat();
+        assert_eq!(decode_key(&raw).to_string(), "balances: 0xfe01");
+    }
+
+    #[test]
+    fn split_namespaces_works() {
+        let key = [key_prefix_nested(&[b"foo", b"bar"]).as_slice(), b"baz"].concat();
+        let foo: &[u8] = b"foo";
+        let bar: &[u8] = b"bar";
+        assert_eq!(split_namespaces(&key, 1), (vec![foo], &key[5..]));
+        assert_eq!(split_namespaces(&key, 5), (vec![foo, bar], &b"baz"[..]));
+        assert_eq!(split_namespaces(&key, 0), (vec![], &key[..]));
+        // raw keys have no namespace
+        assert_eq!(split_namespaces(b"raw", 1), (vec![], &b"raw"[..]));
+    }
+
     #[test]
     fn prefix_get_set() {
         let mut storage = MockStorage::new();
         let prefix = key_prefix(b"foo");
 
         // we use a block scope here to release the &mut before we use it in the next storage
-        set_with_prefix(&mut storage, &prefix, b"bar", b"gotcha");
-        let rfoo = get_with_prefix(&storage, &prefix, b"bar");
+        set_with_prefix(&mut storage, &prefix, b"bar", b"gotcha").unwrap();
+        let rfoo = get_with_prefix(&storage, &prefix, b"bar").unwrap();
         assert_eq!(Some(b"gotcha".to_vec()), rfoo);
 
         // no collisions with other prefixes
         let other_prefix = key_prefix(b"fo");
-        let collision = get_with_prefix(&storage, &other_prefix, b"obar");
+        let collision = get_with_prefix(&storage, &other_prefix, b"obar").unwrap();
         assert_eq!(None, collision);
     }
+
+    #[test]
+    fn prefix_remove() {
+        let mut storage = MockStorage::new();
+        let prefix = key_prefix(b"foo");
+        let other_prefix = key_prefix(b"food");
+
+        set_with_prefix(&mut storage, &prefix, b"bar", b"gotcha").unwrap();
+        set_with_prefix(&mut storage, &other_prefix, b"bar", b"untouched").unwrap();
+
+        remove_with_prefix(&mut storage, &prefix, b"bar").unwrap();
+        assert_eq!(None, get_with_prefix(&storage, &prefix, b"bar").unwrap());
+
+        // other namespaces are not affected
+        let other = get_with_prefix(&storage, &other_prefix, b"bar").unwrap();
+        assert_eq!(Some(b"untouched".to_vec()), other);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn namespace_upper_bound_works() {
+        assert_eq!(
+            namespace_upper_bound(b"\x00\x03foo"),
+            Some(b"\x00\x03fop".to_vec())
+        );
+        assert_eq!(
+            namespace_upper_bound(b"\x00\x01\xFF"),
+            Some(b"\x00\x02".to_vec())
+        );
+        assert_eq!(namespace_upper_bound(b"\xFF\xFF"), None);
+        assert_eq!(namespace_upper_bound(b""), None);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_with_prefix_works() {
+        let mut storage = MockStorage::new();
+        let prefix = key_prefix(b"foo");
+        let other_prefix = key_prefix(b"food");
+
+        set_with_prefix(&mut storage, &prefix, b"bar", b"none").unwrap();
+        set_with_prefix(&mut storage, &prefix, b"snowy", b"day").unwrap();
+        set_with_prefix(&mut storage, &other_prefix, b"moon", b"buggy").unwrap();
+
+        // ensure we get proper result from prefixed_range iterator
+        let mut iter = range_with_prefix(&storage, &prefix, None, None, Order::Descending);
+        let first = iter.next().unwrap().unwrap();
+        assert_eq!(first, (b"snowy".to_vec(), b"day".to_vec()));
+        let second = iter.next().unwrap().unwrap();
+        assert_eq!(second, (b"bar".to_vec(), b"none".to_vec()));
+        assert!(iter.next().is_none());
+
+        // ensure bounds are relative to the namespace
+        let res: StdResult<Vec<KV>> =
+            range_with_prefix(&storage, &prefix, Some(b"c"), None, Order::Ascending).collect();
+        assert_eq!(res.unwrap(), vec![(b"snowy".to_vec(), b"day".to_vec())]);
+        let res: StdResult<Vec<KV>> =
+            range_with_prefix(&storage, &prefix, None, Some(b"snowy"), Order::Ascending).collect();
+        assert_eq!(res.unwrap(), vec![(b"bar".to_vec(), b"none".to_vec())]);
+    }
 }
@@ -0,0 +1,205 @@
+use cosmwasm_std::Order;
+
+use crate::errors::Result;
+
+/// Pagination defines how many items a page returns if the query doesn't specify a limit,
+/// and caps the limit requested by queries
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pagination {
+    pub default_limit: u32,
+    pub max_limit: u32,
+}
+
+impl Pagination {
+    pub const fn new(default_limit: u32, max_limit: u32) -> Self {
+        Pagination {
+            default_limit,
+            max_limit,
+        }
+    }
+
+    /// limit returns the requested limit (default_limit if None), capped at max_limit.
+    /// It is at least 1, as an empty page could not tell if there are more items
+    pub fn limit(&self, requested: Option<u32>) -> u32 {
+        requested
+            .unwrap_or(self.default_limit)
+            .min(self.max_limit)
+            .max(1)
+    }
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Pagination::new(10, 30)
+    }
+}
+
+/// Page is the result of a paginated query. Pass next_start_after as start_after to get the
+/// next page, it is None if there are no more items
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page<K, T> {
+    pub items: Vec<(K, T)>,
+    pub next_start_after: Option<K>,
+}
+
+impl<T> Page<Vec<u8>, T> {
+    // parse_keys converts the raw keys of the page with the given parse function
+    pub(crate) fn parse_keys<K, F>(self, parse: F) -> Result<Page<K, T>>
+    where
+        F: Fn(&[u8]) -> Result<K>,
+    {
+        let next_start_after = match self.next_start_after {
+            Some(k) => Some(parse(&k)?),
+            None => None,
+        };
+        let items: Result<Vec<(K, T)>> = self
+            .items
+            .into_iter()
+            .map(|(k, v)| Ok((parse(&k)?, v)))
+            .collect();
+        Ok(Page {
+            items: items?,
+            next_start_after,
+        })
+    }
+}
+
+// range_after returns the start and end of a range over all keys after start_after
+// (exclusive) in the given order
+pub(crate) fn range_after(
+    start_after: Option<&[u8]>,
+    order: Order,
+) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+    match order {
+        // the first key after start_after
+        Order::Ascending => (start_after.map(|k| [k, b"\x00"].concat()), None),
+        // the range end is exclusive already
+        Order::Descending => (None, start_after.map(|k| k.to_vec())),
+    }
+}
+
+// collect_page reads up to limit items. One more item is read to know if there is a next page
+pub(crate) fn collect_page<T, I>(mut iter: I, limit: u32) -> Result<Page<Vec<u8>, T>>
+where
+    I: Iterator<Item = Result<(Vec<u8>, T)>>,
+{
+    let limit = limit as usize;
+    let items = iter.by_ref().take(limit).collect::<Result<Vec<_>>>()?;
+    let next_start_after = if items.len() == limit && iter.next().is_some() {
+        items.last().map(|(k, _)| k.clone())
+    } else {
+        None
+    };
+    Ok(Page {
+        items,
+        next_start_after,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bucket::{bucket, bucket_read};
+    use crate::keyed_bucket::{keyed_bucket, keyed_bucket_read};
+    use cosmwasm_std::testing::MockStorage;
+
+    fn keys<K: Clone, T>(page: &Page<K, T>) -> Vec<K> {
+        page.items.iter().map(|(k, _)| k.clone()).collect()
+    }
+
+    #[test]
+    fn limits_are_clamped() {
+        let pagination = Pagination::new(10, 30);
+        assert_eq!(pagination.limit(None), 10);
+        assert_eq!(pagination.limit(Some(5)), 5);
+        assert_eq!(pagination.limit(Some(100)), 30);
+        // empty pages are never requested
+        assert_eq!(pagination.limit(Some(0)), 1);
+        assert_eq!(Pagination::new(0, 0).limit(None), 1);
+        let unlimited = Pagination::new(10, std::u32::MAX);
+        assert_eq!(unlimited.limit(Some(std::u32::MAX)), std::u32::MAX);
+        assert_eq!(Pagination::default(), pagination);
+    }
+
+    #[test]
+    fn page_through_bucket() {
+        let mut store = MockStorage::new();
+        let mut numbers = bucket::<_, u32>(b"numbers", &mut store);
+        for i in 0..5u32 {
+            numbers.save(&[b'a' + i as u8], &i).unwrap();
+        }
+
+        let pagination = Pagination::new(2, 3);
+        let reader = bucket_read::<_, u32>(b"numbers", &store);
+        let page = reader
+            .page(None, None, Order::Ascending, &pagination)
+            .unwrap();
+        assert_eq!(page.items, vec![(b"a".to_vec(), 0), (b"b".to_vec(), 1)]);
+        assert_eq!(page.next_start_after, Some(b"b".to_vec()));
+
+        let page = reader
+            .page(Some(b"b"), Some(10), Order::Ascending, &pagination)
+            .unwrap();
+        assert_eq!(
+            keys(&page),
+            vec![b"c".to_vec(), b"d".to_vec(), b"e".to_vec()]
+        );
+        // there is nothing after e
+        assert_eq!(page.next_start_after, None);
+
+        // descending order
+        let page = reader
+            .page(Some(b"c"), Some(10), Order::Descending, &pagination)
+            .unwrap();
+        assert_eq!(keys(&page), vec![b"b".to_vec(), b"a".to_vec()]);
+        assert_eq!(page.next_start_after, None);
+        let page = bucket::<_, u32>(b"numbers", &mut store)
+            .page(None, Some(1), Order::Descending, &pagination)
+            .unwrap();
+        assert_eq!(page.items, vec![(b"e".to_vec(), 4)]);
+        assert_eq!(page.next_start_after, Some(b"e".to_vec()));
+
+        // the maximum limit doesn't overflow
+        let unlimited = Pagination::new(10, std::u32::MAX);
+        let page = bucket_read::<_, u32>(b"numbers", &store)
+            .page(None, Some(std::u32::MAX), Order::Ascending, &unlimited)
+            .unwrap();
+        assert_eq!(page.items.len(), 5);
+        assert_eq!(page.next_start_after, None);
+    }
+
+    #[test]
+    fn page_through_composite_prefix() {
+        let mut store = MockStorage::new();
+        let mut allowances = keyed_bucket::<_, (&str, &str), u64>(b"allowances", &mut store);
+        for spender in &["bob", "carl", "dave", "emma"] {
+            allowances.save(("alice", spender), &100).unwrap();
+        }
+        allowances.save(("bob", "alice"), &5).unwrap();
+
+        let pagination = Pagination::default();
+        let page = allowances
+            .prefix("alice")
+            .page(Some("bob"), Some(2), Order::Ascending, &pagination)
+            .unwrap();
+        assert_eq!(keys(&page), vec!["carl".to_string(), "dave".to_string()]);
+        assert_eq!(page.next_start_after, Some("dave".to_string()));
+
+        let reader = keyed_bucket_read::<_, (&str, &str), u64>(b"allowances", &store);
+        let alice = reader.prefix("alice");
+        let page = alice
+            .page(Some("dave"), Some(2), Order::Ascending, &pagination)
+            .unwrap();
+        // the entries of bob are not part of the prefix
+        assert_eq!(page.items, vec![("emma".to_string(), 100)]);
+        assert_eq!(page.next_start_after, None);
+
+        let page = reader
+            .page(None, None, Order::Descending, &pagination)
+            .unwrap();
+        assert_eq!(page.items.len(), 5);
+        // the first element is length-prefixed, so shorter owners sort first
+        assert_eq!(page.items[0].0, ("alice".to_string(), "emma".to_string()));
+        assert_eq!(page.items[4].0, ("bob".to_string(), "alice".to_string()));
+    }
+}
@@ -1,6 +1,14 @@
-use cosmwasm::traits::{ReadonlyStorage, Storage};
+#[cfg(feature = "iterator")]
+use cosmwasm_std::{Order, KV};
+use cosmwasm_std::{ReadonlyStorage, StdResult, Storage};
 
-use crate::namespace_helpers::{get_with_prefix, key_prefix, key_prefix_nested, set_with_prefix};
+use crate::errors::Result;
+#[cfg(feature = "iterator")]
+use crate::namespace_helpers::range_with_prefix;
+use crate::namespace_helpers::{
+    get_with_prefix, key_prefix, key_prefix_nested, remove_with_prefix, set_with_prefix,
+    try_key_prefix, try_key_prefix_nested,
+};
 
 // prefixed_read is a helper function for less verbose usage
 pub fn prefixed_read<'a, T: ReadonlyStorage>(
@@ -36,12 +44,46 @@ impl<'a, T: ReadonlyStorage> ReadonlyPrefixedStorage<'a, T> {
             storage,
         }
     }
+
+    /// try_new works like new, but returns an error instead of panicking if the namespace
+    /// is longer than 0xFFFF bytes. Use it for namespaces derived from user input
+    pub fn try_new(namespace: &[u8], storage: &'a T) -> Result<Self> {
+        Ok(ReadonlyPrefixedStorage {
+            prefix: try_key_prefix(namespace)?,
+            storage,
+        })
+    }
+
+    /// try_multilevel works like multilevel, but returns an error instead of panicking
+    /// if any namespace is longer than 0xFFFF bytes
+    pub fn try_multilevel(namespaces: &[&[u8]], storage: &'a T) -> Result<Self> {
+        Ok(ReadonlyPrefixedStorage {
+            prefix: try_key_prefix_nested(namespaces)?,
+            storage,
+        })
+    }
 }
 
 impl<'a, T: ReadonlyStorage> ReadonlyStorage for ReadonlyPrefixedStorage<'a, T> {
-    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+    fn get(&self, key: &[u8]) -> StdResult<Option<Vec<u8>>> {
         get_with_prefix(self.storage, &self.prefix, key)
     }
+
+    #[cfg(feature = "iterator")]
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> StdResult<Box<dyn Iterator<Item = StdResult<KV>> + 'b>> {
+        Ok(range_with_prefix(
+            self.storage,
+            &self.prefix,
+            start,
+            end,
+            order,
+        ))
+    }
 }
 
 pub struct PrefixedStorage<'a, T: Storage> {
@@ -65,24 +107,79 @@ impl<'a, T: Storage> PrefixedStorage<'a, T> {
             storage,
         }
     }
+
+    /// try_new works like new, but returns an error instead of panicking if the namespace
+    /// is longer than 0xFFFF bytes. Use it for namespaces derived from user input
+    pub fn try_new(namespace: &[u8], storage: &'a mut T) -> Result<Self> {
+        Ok(PrefixedStorage {
+            prefix: try_key_prefix(namespace)?,
+            storage,
+        })
+    }
+
+    /// try_multilevel works like multilevel, but returns an error instead of panicking
+    /// if any namespace is longer than 0xFFFF bytes
+    pub fn try_multilevel(namespaces: &[&[u8]], storage: &'a mut T) -> Result<Self> {
+        Ok(PrefixedStorage {
+            prefix: try_key_prefix_nested(namespaces)?,
+            storage,
+        })
+    }
 }
 
 impl<'a, T: Storage> ReadonlyStorage for PrefixedStorage<'a, T> {
-    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+    fn get(&self, key: &[u8]) -> StdResult<Option<Vec<u8>>> {
         get_with_prefix(self.storage, &self.prefix, key)
     }
+
+    #[cfg(feature = "iterator")]
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> StdResult<Box<dyn Iterator<Item = StdResult<KV>> + 'b>> {
+        Ok(range_with_prefix(
+            self.storage,
+            &self.prefix,
+            start,
+            end,
+            order,
+        ))
+    }
 }
 
 impl<'a, T: Storage> Storage for PrefixedStorage<'a, T> {
-    fn set(&mut self, key: &[u8], value: &[u8]) {
+    fn set(&mut self, key: &[u8], value: &[u8]) -> StdResult<()> {
         set_with_prefix(self.storage, &self.prefix, key, value)
     }
+
+    fn remove(&mut self, key: &[u8]) -> StdResult<()> {
+        remove_with_prefix(self.storage, &self.prefix, key)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use cosmwasm::mock::MockStorage;
+    use cosmwasm_std::testing::MockStorage;
+
+    #[test]
+    fn try_new_rejects_long_namespace() {
+        let mut storage = MockStorage::new();
+        let long_namespace = vec![b'a'; 0x10000];
+        assert!(PrefixedStorage::try_new(&long_namespace, &mut storage).is_err());
+        assert!(PrefixedStorage::try_multilevel(&[b"foo", &long_namespace], &mut storage).is_err());
+        assert!(ReadonlyPrefixedStorage::try_new(&long_namespace, &storage).is_err());
+
+        let mut foo = PrefixedStorage::try_multilevel(&[b"foo", b"bar"], &mut storage).unwrap();
+        foo.set(b"baz", b"gotcha").unwrap();
+        let reader = ReadonlyPrefixedStorage::try_new(b"foo", &storage).unwrap();
+        assert_eq!(
+            reader.get(b"\x00\x03barbaz").unwrap(),
+            Some(b"gotcha".to_vec())
+        );
+    }
 
     #[test]
     fn prefix_safe() {
@@ -90,20 +187,20 @@ mod test {
 
         // we use a block scope here to release the &mut before we use it in the next storage
         let mut foo = PrefixedStorage::new(b"foo", &mut storage);
-        foo.set(b"bar", b"gotcha");
-        assert_eq!(Some(b"gotcha".to_vec()), foo.get(b"bar"));
+        foo.set(b"bar", b"gotcha").unwrap();
+        assert_eq!(Some(b"gotcha".to_vec()), foo.get(b"bar").unwrap());
 
         // try readonly correctly
         let rfoo = ReadonlyPrefixedStorage::new(b"foo", &storage);
-        assert_eq!(Some(b"gotcha".to_vec()), rfoo.get(b"bar"));
+        assert_eq!(Some(b"gotcha".to_vec()), rfoo.get(b"bar").unwrap());
 
         // no collisions with other prefixes
         let fo = ReadonlyPrefixedStorage::new(b"fo", &storage);
-        assert_eq!(None, fo.get(b"obar"));
+        assert_eq!(None, fo.get(b"obar").unwrap());
 
         // Note: explicit scoping is not required, but you must not refer to `foo` anytime after you
         // initialize a different PrefixedStorage. Uncomment this to see errors:
-        //        assert_eq!(Some(b"gotcha".to_vec()), foo.get(b"bar"));
+        //        assert_eq!(Some(b"gotcha".to_vec()), foo.get(b"bar").unwrap());
     }
 
     #[test]
@@ -113,18 +210,124 @@ mod test {
         // set with nested
         let mut foo = PrefixedStorage::new(b"foo", &mut storage);
         let mut bar = PrefixedStorage::new(b"bar", &mut foo);
-        bar.set(b"baz", b"winner");
+        bar.set(b"baz", b"winner").unwrap();
 
         // we can nest them the same encoding with one operation
         let loader = ReadonlyPrefixedStorage::multilevel(&[b"foo", b"bar"], &storage);
-        assert_eq!(Some(b"winner".to_vec()), loader.get(b"baz"));
+        assert_eq!(Some(b"winner".to_vec()), loader.get(b"baz").unwrap());
 
         // set with multilevel
         let mut foobar = PrefixedStorage::multilevel(&[b"foo", b"bar"], &mut storage);
-        foobar.set(b"second", b"time");
+        foobar.set(b"second", b"time").unwrap();
 
         let a = ReadonlyPrefixedStorage::new(b"foo", &storage);
         let b = ReadonlyPrefixedStorage::new(b"bar", &a);
-        assert_eq!(Some(b"time".to_vec()), b.get(b"second"));
+        assert_eq!(Some(b"time".to_vec()), b.get(b"second").unwrap());
+    }
+
+    #[test]
+    fn remove_works() {
+        let mut storage = MockStorage::new();
+
+        let mut foo = PrefixedStorage::new(b"foo", &mut storage);
+        foo.set(b"bar", b"gotcha").unwrap();
+        foo.set(b"baz", b"stays").unwrap();
+        foo.remove(b"bar").unwrap();
+        assert_eq!(None, foo.get(b"bar").unwrap());
+        assert_eq!(Some(b"stays".to_vec()), foo.get(b"baz").unwrap());
+
+        // removing a missing key is a no-op
+        foo.remove(b"bar").unwrap();
+        assert_eq!(None, foo.get(b"bar").unwrap());
+
+        let mut fo = PrefixedStorage::new(b"fo", &mut storage);
+        fo.set(b"obar", b"neighbour").unwrap();
+        fo.remove(b"obaz").unwrap();
+
+        // neighbouring namespaces are untouched
+        let rfo = ReadonlyPrefixedStorage::new(b"fo", &storage);
+        assert_eq!(Some(b"neighbour".to_vec()), rfo.get(b"obar").unwrap());
+        let rfoo = ReadonlyPrefixedStorage::new(b"foo", &storage);
+        assert_eq!(Some(b"stays".to_vec()), rfoo.get(b"baz").unwrap());
+    }
+
+    // generic code that only knows about ReadonlyStorage
+    #[cfg(feature = "iterator")]
+    fn all_keys<S: ReadonlyStorage>(storage: &S, order: Order) -> Vec<Vec<u8>> {
+        storage
+            .range(None, None, order)
+            .unwrap()
+            .map(|kv| kv.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_works() {
+        let mut storage = MockStorage::new();
+
+        let mut foo = PrefixedStorage::new(b"foo", &mut storage);
+        foo.set(b"one", b"1").unwrap();
+        foo.set(b"two", b"2").unwrap();
+        foo.set(b"three", b"3").unwrap();
+
+        // keys are relative to the namespace
+        let res: StdResult<Vec<KV>> = foo.range(None, None, Order::Ascending).unwrap().collect();
+        let expected = vec![
+            (b"one".to_vec(), b"1".to_vec()),
+            (b"three".to_vec(), b"3".to_vec()),
+            (b"two".to_vec(), b"2".to_vec()),
+        ];
+        assert_eq!(res.unwrap(), expected);
+
+        // neighbouring namespaces don't leak in
+        let mut fo = PrefixedStorage::new(b"fo", &mut storage);
+        fo.set(b"oone", b"other").unwrap();
+        let mut food = PrefixedStorage::new(b"food", &mut storage);
+        food.set(b"one", b"other").unwrap();
+
+        let rfoo = ReadonlyPrefixedStorage::new(b"foo", &storage);
+        assert_eq!(
+            all_keys(&rfoo, Order::Descending),
+            vec![b"two".to_vec(), b"three".to_vec(), b"one".to_vec()]
+        );
+
+        // bounds are relative to the namespace as well
+        let res: StdResult<Vec<KV>> = rfoo
+            .range(Some(b"p"), Some(b"two"), Order::Ascending)
+            .unwrap()
+            .collect();
+        assert_eq!(res.unwrap(), vec![(b"three".to_vec(), b"3".to_vec())]);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_multi_level() {
+        let mut storage = MockStorage::new();
+
+        let mut foobar = PrefixedStorage::multilevel(&[b"foo", b"bar"], &mut storage);
+        foobar.set(b"one", b"1").unwrap();
+        foobar.set(b"two", b"2").unwrap();
+        let mut foobaz = PrefixedStorage::multilevel(&[b"foo", b"baz"], &mut storage);
+        foobaz.set(b"three", b"3").unwrap();
+
+        // multilevel and nested prefixes give the same view
+        let multi = ReadonlyPrefixedStorage::multilevel(&[b"foo", b"bar"], &storage);
+        let foo = ReadonlyPrefixedStorage::new(b"foo", &storage);
+        let nested = ReadonlyPrefixedStorage::new(b"bar", &foo);
+        let expected = vec![b"one".to_vec(), b"two".to_vec()];
+        assert_eq!(all_keys(&multi, Order::Ascending), expected);
+        assert_eq!(all_keys(&nested, Order::Ascending), expected);
+
+        // the outer level sees the length-prefixed inner namespaces
+        let keys = all_keys(&foo, Order::Ascending);
+        assert_eq!(
+            keys,
+            vec![
+                b"\x00\x03barone".to_vec(),
+                b"\x00\x03bartwo".to_vec(),
+                b"\x00\x03bazthree".to_vec(),
+            ]
+        );
     }
 }
@@ -1,31 +1,138 @@
-use cosmwasm::errors::Result;
-use cosmwasm::traits::Storage;
+use serde::{de::DeserializeOwned, ser::Serialize};
 
-use crate::Singleton;
+use cosmwasm_std::{ReadonlyStorage, Storage};
+
+use crate::codec::Codec;
+use crate::errors::{Overflow, Result};
+use crate::{ReadonlySingleton, Singleton};
+
+/// SequenceValue is implemented by the unsigned integer types that can be used as a sequence.
+/// u128 is not supported, as the default Json codec cannot serialize it
+pub trait SequenceValue: Serialize + DeserializeOwned + Copy + Default {
+    fn one() -> Self;
+    fn checked_add(self, other: Self) -> Option<Self>;
+}
+
+macro_rules! sequence_value {
+    ($($t:ty),*) => {
+        $(
+            impl SequenceValue for $t {
+                fn one() -> Self {
+                    1
+                }
+
+                fn checked_add(self, other: Self) -> Option<Self> {
+                    <$t>::checked_add(self, other)
+                }
+            }
+        )*
+    };
+}
+
+sequence_value!(u8, u16, u32, u64);
+
+/// SequenceSource is implemented by Singleton and ReadonlySingleton, so currval can read
+/// a sequence from both
+pub trait SequenceSource<T> {
+    fn may_load_value(&self) -> Result<Option<T>>;
+}
+
+impl<'a, S: Storage, T, C> SequenceSource<T> for Singleton<'a, S, T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    fn may_load_value(&self) -> Result<Option<T>> {
+        self.may_load()
+    }
+}
+
+impl<'a, S: ReadonlyStorage, T, C> SequenceSource<T> for ReadonlySingleton<'a, S, T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    fn may_load_value(&self) -> Result<Option<T>> {
+        self.may_load()
+    }
+}
 
 /// Sequence creates a custom Singleton to hold an empty sequence
 pub fn sequence<'a, S: Storage>(storage: &'a mut S, key: &[u8]) -> Singleton<'a, S, u64> {
     Singleton::new(storage, key)
 }
 
+/// sequence_read creates a ReadonlySingleton to query the sequence with currval
+pub fn sequence_read<'a, S: ReadonlyStorage>(
+    storage: &'a S,
+    key: &[u8],
+) -> ReadonlySingleton<'a, S, u64> {
+    ReadonlySingleton::new(storage, key)
+}
+
+/// typed_sequence works like sequence, but the value type can be any unsigned integer,
+/// e.g. `typed_sequence::<_, u32>(storage, b"seq")`
+pub fn typed_sequence<'a, S: Storage, T: SequenceValue>(
+    storage: &'a mut S,
+    key: &[u8],
+) -> Singleton<'a, S, T> {
+    Singleton::new(storage, key)
+}
+
+/// typed_sequence_read creates a ReadonlySingleton to query a typed_sequence with currval
+pub fn typed_sequence_read<'a, S: ReadonlyStorage, T: SequenceValue>(
+    storage: &'a S,
+    key: &[u8],
+) -> ReadonlySingleton<'a, S, T> {
+    ReadonlySingleton::new(storage, key)
+}
+
 /// currval returns the last value returned by nextval. If the sequence has never been used,
 /// then it will return 0.
-pub fn currval<S: Storage>(seq: &Singleton<S, u64>) -> Result<u64> {
-    Ok(seq.may_load()?.unwrap_or_default())
+pub fn currval<T: SequenceValue, Q: SequenceSource<T>>(seq: &Q) -> Result<T> {
+    Ok(seq.may_load_value()?.unwrap_or_default())
 }
 
 /// nextval increments the counter by 1 and returns the new value.
 /// On the first time it is called (no sequence info in db) it will return 1.
-pub fn nextval<S: Storage>(seq: &mut Singleton<S, u64>) -> Result<u64> {
-    let val = currval(&seq)? + 1;
+/// Returns an Overflow error instead of wrapping around.
+pub fn nextval<S: Storage, T: SequenceValue, C: Codec>(seq: &mut Singleton<S, T, C>) -> Result<T> {
+    nextval_by(seq, T::one())
+}
+
+/// nextval_by increments the counter by step and returns the new value. This reserves
+/// the block of values new - step + 1 ..= new, e.g. to assign ids to a batch of items.
+/// Returns an Overflow error instead of wrapping around.
+pub fn nextval_by<S: Storage, T: SequenceValue, C: Codec>(
+    seq: &mut Singleton<S, T, C>,
+    step: T,
+) -> Result<T> {
+    let val = match currval(seq)?.checked_add(step) {
+        Some(val) => val,
+        None => {
+            return Overflow {
+                msg: "sequence exhausted",
+            }
+            .fail()
+        }
+    };
     seq.save(&val)?;
     Ok(val)
 }
 
+/// reset sets the sequence to the given value, so the next call to nextval returns value + 1
+pub fn reset<S: Storage, T: SequenceValue, C: Codec>(
+    seq: &mut Singleton<S, T, C>,
+    value: T,
+) -> Result<()> {
+    seq.save(&value)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use cosmwasm::mock::MockStorage;
+    use crate::errors::StorageError;
+    use cosmwasm_std::testing::MockStorage;
 
     #[test]
     fn walk_through_sequence() {
@@ -69,5 +176,62 @@ mod test {
 
         assert_eq!(currval(&seq).unwrap(), 20);
         assert_eq!(nextval(&mut seq).unwrap(), 21);
+
+        reset(&mut seq, 5).unwrap();
+        assert_eq!(nextval(&mut seq).unwrap(), 6);
+    }
+
+    #[test]
+    fn read_only_u64_sequence() {
+        let mut store = MockStorage::new();
+        nextval(&mut sequence(&mut store, b"seq")).unwrap();
+        assert_eq!(currval(&sequence_read(&store, b"seq")).unwrap(), 1);
+    }
+
+    #[test]
+    fn read_only_currval() {
+        let mut store = MockStorage::new();
+        nextval(&mut typed_sequence::<_, u32>(&mut store, b"seq")).unwrap();
+
+        let seq = typed_sequence_read::<_, u32>(&store, b"seq");
+        assert_eq!(currval(&seq).unwrap(), 1);
+        let unused = typed_sequence_read::<_, u32>(&store, b"other");
+        assert_eq!(currval(&unused).unwrap(), 0);
+    }
+
+    #[test]
+    fn step_by_blocks() {
+        let mut store = MockStorage::new();
+        let mut seq = typed_sequence::<_, u64>(&mut store, b"seq");
+
+        // reserves 1..=10
+        assert_eq!(nextval_by(&mut seq, 10).unwrap(), 10);
+        assert_eq!(nextval(&mut seq).unwrap(), 11);
+        // a step of 0 reserves nothing
+        assert_eq!(nextval_by(&mut seq, 0).unwrap(), 11);
+        assert_eq!(currval(&seq).unwrap(), 11);
+
+        // large values work as well
+        reset(&mut seq, u64::from(std::u32::MAX)).unwrap();
+        assert_eq!(nextval(&mut seq).unwrap(), u64::from(std::u32::MAX) + 1);
+    }
+
+    #[test]
+    fn overflow_errors() {
+        let mut store = MockStorage::new();
+        let mut seq = typed_sequence::<_, u8>(&mut store, b"seq");
+
+        reset(&mut seq, 250).unwrap();
+        assert_eq!(nextval_by(&mut seq, 5).unwrap(), 255);
+        match nextval(&mut seq) {
+            Err(StorageError::Overflow { .. }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        // nothing was written
+        assert_eq!(currval(&seq).unwrap(), 255);
+
+        reset(&mut seq, 0).unwrap();
+        assert!(nextval_by(&mut seq, 200).is_ok());
+        assert!(nextval_by(&mut seq, 100).is_err());
     }
 }
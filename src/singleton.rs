@@ -1,11 +1,13 @@
 use serde::{de::DeserializeOwned, ser::Serialize};
 use std::marker::PhantomData;
 
-use cosmwasm::errors::Result;
-use cosmwasm::traits::{ReadonlyStorage, Storage};
+use cosmwasm_std::{ReadonlyStorage, Storage};
 
-use crate::namespace_helpers::key_prefix;
-use crate::type_helpers::{may_deserialize, must_deserialize, serialize};
+use crate::cache::{may_load_cached, must_load_cached, TypedCache};
+use crate::codec::{Codec, Json};
+use crate::errors::{Result, StorageError};
+use crate::namespace_helpers::{key_prefix, try_key_prefix};
+use crate::type_helpers::{may_deserialize, must_deserialize};
 
 // singleton is a helper function for less verbose usage
 pub fn singleton<'a, S: Storage, T>(storage: &'a mut S, key: &[u8]) -> Singleton<'a, S, T>
@@ -30,104 +32,193 @@ where
 /// work on one single value. It performs the key_prefix transformation
 /// on the given name to ensure no collisions, and then provides the standard
 /// TypedStorage accessors, without requiring a key (which is defined in the constructor)
-pub struct Singleton<'a, S: Storage, T>
+pub struct Singleton<'a, S: Storage, T, C = Json>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     storage: &'a mut S,
     key: Vec<u8>,
     // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
     data: PhantomData<&'a T>,
+    codec: PhantomData<C>,
 }
 
-impl<'a, S: Storage, T> Singleton<'a, S, T>
+impl<'a, S: Storage, T, C> Singleton<'a, S, T, C>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     pub fn new(storage: &'a mut S, key: &[u8]) -> Self {
         Singleton {
             storage,
             key: key_prefix(key),
             data: PhantomData,
+            codec: PhantomData,
         }
     }
 
+    /// try_new works like new, but returns an error instead of panicking if the key
+    /// is longer than 0xFFFF bytes. Use it for keys derived from user input
+    pub fn try_new(storage: &'a mut S, key: &[u8]) -> Result<Self> {
+        Ok(Singleton {
+            storage,
+            key: try_key_prefix(key)?,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
     /// save will serialize the model and store, returns an error on serialization issues
     pub fn save(&mut self, data: &T) -> Result<()> {
-        self.storage.set(&self.key, &serialize(data)?);
+        self.storage.set(&self.key, &C::encode(data)?)?;
+        Ok(())
+    }
+
+    /// remove will delete the stored value. Removing a missing value is a no-op
+    pub fn remove(&mut self) -> Result<()> {
+        self.storage.remove(&self.key)?;
         Ok(())
     }
 
     /// load will return an error if no data is set at the given key, or on parse error
     pub fn load(&self) -> Result<T> {
-        let value = self.storage.get(&self.key);
-        must_deserialize(&value)
+        let value = self.storage.get(&self.key)?;
+        must_deserialize::<C, _>(&self.key, b"", &value)
     }
 
     /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
     /// returns an error on issues parsing
     pub fn may_load(&self) -> Result<Option<T>> {
-        let value = self.storage.get(&self.key);
-        may_deserialize(&value)
+        let value = self.storage.get(&self.key)?;
+        may_deserialize::<C, _>(&self.key, b"", &value)
     }
 
     /// update will load the data, perform the specified action, and store the result
-    /// in the database. This is shorthand for some common sequences, which may be useful
+    /// in the database. This is shorthand for some common sequences, which may be useful.
+    /// Returns NotFound if the singleton was never saved, see `may_update` to initialize it
     ///
-    /// This is the least stable of the APIs, and definitely needs some usage
-    pub fn update(&mut self, action: &dyn Fn(T) -> Result<T>) -> Result<T> {
+    /// The action may return any error type that a StorageError can be converted into
+    pub fn update<A, E>(&mut self, action: A) -> Result<T, E>
+    where
+        A: FnOnce(T) -> Result<T, E>,
+        E: From<StorageError>,
+    {
         let input = self.load()?;
         let output = action(input)?;
         self.save(&output)?;
         Ok(output)
     }
+
+    /// may_update works like update, but passes None to the action if there is no data yet
+    pub fn may_update<A, E>(&mut self, action: A) -> Result<T, E>
+    where
+        A: FnOnce(Option<T>) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.may_load()?;
+        let output = action(input)?;
+        self.save(&output)?;
+        Ok(output)
+    }
+}
+
+impl<'a, S, T, C> Singleton<'a, S, T, C>
+where
+    S: Storage + TypedCache,
+    T: Serialize + DeserializeOwned + Clone + 'static,
+    C: Codec + 'static,
+{
+    /// load_cached works like load, but memoizes the parsed value in the storage (see CachedStorage),
+    /// so loading the singleton again returns a clone without parsing
+    pub fn load_cached(&self) -> Result<T> {
+        must_load_cached::<_, C, T>(self.storage, &self.key, b"")
+    }
+
+    /// may_load_cached works like may_load, but memoizes the parsed value in the storage
+    pub fn may_load_cached(&self) -> Result<Option<T>> {
+        may_load_cached::<_, C, T>(self.storage, &self.key, b"")
+    }
 }
 
 /// ReadonlySingleton only requires a ReadonlyStorage and exposes only the
 /// methods of Singleton that don't modify state.
-pub struct ReadonlySingleton<'a, S: ReadonlyStorage, T>
+pub struct ReadonlySingleton<'a, S: ReadonlyStorage, T, C = Json>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     storage: &'a S,
     key: Vec<u8>,
     // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
     data: PhantomData<&'a T>,
+    codec: PhantomData<C>,
 }
 
-impl<'a, S: ReadonlyStorage, T> ReadonlySingleton<'a, S, T>
+impl<'a, S: ReadonlyStorage, T, C> ReadonlySingleton<'a, S, T, C>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     pub fn new(storage: &'a S, key: &[u8]) -> Self {
         ReadonlySingleton {
             storage,
             key: key_prefix(key),
             data: PhantomData,
+            codec: PhantomData,
         }
     }
 
+    /// try_new works like new, but returns an error instead of panicking if the key
+    /// is longer than 0xFFFF bytes. Use it for keys derived from user input
+    pub fn try_new(storage: &'a S, key: &[u8]) -> Result<Self> {
+        Ok(ReadonlySingleton {
+            storage,
+            key: try_key_prefix(key)?,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
     /// load will return an error if no data is set at the given key, or on parse error
     pub fn load(&self) -> Result<T> {
-        let value = self.storage.get(&self.key);
-        must_deserialize(&value)
+        let value = self.storage.get(&self.key)?;
+        must_deserialize::<C, _>(&self.key, b"", &value)
     }
 
     /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
     /// returns an error on issues parsing
     pub fn may_load(&self) -> Result<Option<T>> {
-        let value = self.storage.get(&self.key);
-        may_deserialize(&value)
+        let value = self.storage.get(&self.key)?;
+        may_deserialize::<C, _>(&self.key, b"", &value)
+    }
+}
+
+impl<'a, S, T, C> ReadonlySingleton<'a, S, T, C>
+where
+    S: ReadonlyStorage + TypedCache,
+    T: Serialize + DeserializeOwned + Clone + 'static,
+    C: Codec + 'static,
+{
+    /// load_cached works like load, but memoizes the parsed value in the storage (see CachedStorage),
+    /// so loading the singleton again returns a clone without parsing
+    pub fn load_cached(&self) -> Result<T> {
+        must_load_cached::<_, C, T>(self.storage, &self.key, b"")
+    }
+
+    /// may_load_cached works like may_load, but memoizes the parsed value in the storage
+    pub fn may_load_cached(&self) -> Result<Option<T>> {
+        may_load_cached::<_, C, T>(self.storage, &self.key, b"")
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use cosmwasm::mock::MockStorage;
+    use cosmwasm_std::testing::MockStorage;
     use serde::{Deserialize, Serialize};
 
-    use cosmwasm::errors::{Error, Unauthorized};
+    use cosmwasm_std::{unauthorized, StdError};
 
     #[derive(Serialize, Deserialize, PartialEq, Debug)]
     struct Config {
@@ -135,6 +226,19 @@ mod test {
         pub max_tokens: i32,
     }
 
+    #[test]
+    fn try_new_rejects_long_key() {
+        let mut store = MockStorage::new();
+        let long_key = vec![b'a'; 0x10000];
+        assert!(Singleton::<_, u32>::try_new(&mut store, &long_key).is_err());
+        assert!(ReadonlySingleton::<_, u32>::try_new(&store, &long_key).is_err());
+
+        let mut config = Singleton::<_, u32>::try_new(&mut store, b"config").unwrap();
+        config.save(&17).unwrap();
+        let reader = ReadonlySingleton::<_, u32>::try_new(&store, b"config").unwrap();
+        assert_eq!(reader.load().unwrap(), 17);
+    }
+
     #[test]
     fn save_and_load() {
         let mut store = MockStorage::new();
@@ -152,6 +256,53 @@ mod test {
         assert_eq!(cfg, single.load().unwrap());
     }
 
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn custom_codec() {
+        use crate::CompactBincode;
+
+        let mut store = MockStorage::new();
+        let mut single = Singleton::<_, Config, CompactBincode>::new(&mut store, b"config");
+        let cfg = Config {
+            owner: "admin".to_string(),
+            max_tokens: 1234,
+        };
+        single.save(&cfg).unwrap();
+        assert_eq!(cfg, single.load().unwrap());
+
+        let reader = ReadonlySingleton::<_, Config, CompactBincode>::new(&store, b"config");
+        assert_eq!(cfg, reader.load().unwrap());
+        // json can't read it
+        assert!(singleton_read::<_, Config>(&store, b"config")
+            .load()
+            .is_err());
+    }
+
+    #[test]
+    fn remove_works() {
+        let mut store = MockStorage::new();
+        let cfg = Config {
+            owner: "admin".to_string(),
+            max_tokens: 1234,
+        };
+        singleton::<_, Config>(&mut store, b"config2")
+            .save(&cfg)
+            .unwrap();
+
+        let mut single = singleton::<_, Config>(&mut store, b"config");
+        single.save(&cfg).unwrap();
+        single.remove().unwrap();
+        assert_eq!(single.may_load().unwrap(), None);
+
+        // removing twice is fine
+        single.remove().unwrap();
+        assert_eq!(single.may_load().unwrap(), None);
+
+        // other singletons are untouched
+        let other = singleton_read::<_, Config>(&store, b"config2");
+        assert_eq!(other.load().unwrap(), cfg);
+    }
+
     #[test]
     fn isolated_reads() {
         let mut store = MockStorage::new();
@@ -181,7 +332,7 @@ mod test {
         };
         writer.save(&cfg).unwrap();
 
-        let output = writer.update(&|mut c| {
+        let output = writer.update(|mut c| -> Result<_> {
             c.max_tokens *= 2;
             Ok(c)
         });
@@ -204,11 +355,49 @@ mod test {
         };
         writer.save(&cfg).unwrap();
 
-        let output = writer.update(&|_c| Unauthorized {}.fail());
+        let output = writer.update(|_c| Err(unauthorized()));
         match output {
-            Err(Error::Unauthorized { .. }) => {}
+            Err(StdError::Unauthorized { .. }) => {}
             _ => panic!("Unexpected output: {:?}", output),
         }
         assert_eq!(writer.load().unwrap(), cfg);
     }
+
+    #[test]
+    fn may_update_initializes() {
+        let mut store = MockStorage::new();
+        let mut writer = singleton::<_, Config>(&mut store, b"config");
+
+        let output = writer.update(Ok);
+        match output {
+            Err(err @ StorageError::NotFound { .. }) => assert_eq!(
+                err.to_string(),
+                "cw_storage::singleton::test::Config not found in config"
+            ),
+            _ => panic!("Unexpected output: {:?}", output),
+        }
+
+        // the owner is moved into the closure
+        let owner = "admin".to_string();
+        let output = writer
+            .may_update(move |c| -> Result<_> {
+                assert_eq!(c, None);
+                Ok(Config {
+                    owner,
+                    max_tokens: 100,
+                })
+            })
+            .unwrap();
+        assert_eq!(output.owner, "admin");
+
+        let output = writer
+            .may_update(|c| -> Result<_> {
+                let mut c = c.unwrap();
+                c.max_tokens += 1;
+                Ok(c)
+            })
+            .unwrap();
+        assert_eq!(output.max_tokens, 101);
+        assert_eq!(writer.load().unwrap(), output);
+    }
 }
@@ -0,0 +1,478 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::marker::PhantomData;
+
+use cosmwasm_std::{Order, ReadonlyStorage, Storage};
+
+use crate::bucket::{Bucket, ReadonlyBucket};
+use crate::errors::{NotCheckpointed, Result, StorageError};
+use crate::keys::PrimaryKey;
+use crate::namespace_helpers::{
+    key_prefix_nested, range_with_prefix, try_key_prefix, try_key_prefix_nested,
+};
+
+// Snapshot structures share one layout, where a SnapshotSingleton is stored like a SnapshotBucket
+// with a single entry under the empty key:
+//
+//   [namespace, "primary"] + key                  => current value
+//   [namespace, "changelog", key] + height        => ChangeSet with the value before the first change at height
+//   [namespace, "checkpoints"] + height           => number of add_checkpoint calls for height
+//
+// Heights are u64 stored big endian, so the changelog of a key is sorted by height
+pub(crate) const PRIMARY_NAMESPACE: &[u8] = b"primary";
+const CHANGELOG_NAMESPACE: &[u8] = b"changelog";
+const CHECKPOINTS_NAMESPACE: &[u8] = b"checkpoints";
+
+pub fn snapshot_bucket<'a, S: Storage, T>(
+    namespace: &[u8],
+    storage: &'a mut S,
+    strategy: Strategy,
+) -> SnapshotBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    SnapshotBucket::new(namespace, storage, strategy)
+}
+
+pub fn snapshot_bucket_read<'a, S: ReadonlyStorage, T>(
+    namespace: &[u8],
+    storage: &'a S,
+    strategy: Strategy,
+) -> ReadonlySnapshotBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    ReadonlySnapshotBucket::new(namespace, storage, strategy)
+}
+
+/// Strategy defines at which heights the previous values are recorded, and therefore
+/// at which heights historical queries are possible
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// Record every change, all heights can be queried
+    EveryBlock,
+    /// Never record anything, historical queries always fail
+    Never,
+    /// Only record what is needed to query heights registered with `add_checkpoint`
+    Selected,
+}
+
+// ChangeSet stores the value before the first change at a given height (None if it was unset)
+#[derive(Serialize, Deserialize)]
+struct ChangeSet<T> {
+    old: Option<T>,
+}
+
+/// SnapshotBucket works like a Bucket, but keeps a changelog of previous values, so the value
+/// of a key can be queried at a past height (e.g. the voting power when a proposal was created).
+///
+/// Every write takes the current height. Before the first change of a key at a height,
+/// its old value is stored in the changelog under (key, height). `may_load_at_height` then
+/// returns the value as it was at the beginning of the given height, i.e. before any change
+/// at that height. Which changes are recorded is controlled by the Strategy.
+///
+/// Heights must never decrease between writes. As the changelog is namespaced by key, keys may
+/// be at most 0xFFFF bytes long, writing a longer key returns an error.
+pub struct SnapshotBucket<'a, S: Storage, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    storage: &'a mut S,
+    namespace: Vec<u8>,
+    strategy: Strategy,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+}
+
+impl<'a, S: Storage, T> SnapshotBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(namespace: &[u8], storage: &'a mut S, strategy: Strategy) -> Self {
+        SnapshotBucket {
+            storage,
+            namespace: namespace.to_vec(),
+            strategy,
+            data: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a mut S, strategy: Strategy) -> Result<Self> {
+        try_key_prefix(namespace)?;
+        Ok(Self::new(namespace, storage, strategy))
+    }
+
+    /// save stores the value at the given height, recording the previous value if needed
+    pub fn save(&mut self, key: &[u8], data: &T, height: u64) -> Result<()> {
+        record_change::<_, T>(self.storage, &self.namespace, self.strategy, key, height)?;
+        self.primary().save(key, data)
+    }
+
+    /// remove deletes the value at the given height, recording the previous value if needed
+    pub fn remove(&mut self, key: &[u8], height: u64) -> Result<()> {
+        record_change::<_, T>(self.storage, &self.namespace, self.strategy, key, height)?;
+        self.primary().remove(key)?;
+        Ok(())
+    }
+
+    /// load will return an error if no data is set at the given key, or on parse error
+    pub fn load(&self, key: &[u8]) -> Result<T> {
+        self.primary_read().load(key)
+    }
+
+    /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self, key: &[u8]) -> Result<Option<T>> {
+        self.primary_read().may_load(key)
+    }
+
+    /// may_load_at_height returns the value at the beginning of the given height.
+    /// Returns NotCheckpointed if the strategy doesn't allow queries at this height
+    pub fn may_load_at_height(&self, key: &[u8], height: u64) -> Result<Option<T>> {
+        may_load_at_height(self.storage, &self.namespace, self.strategy, key, height)
+    }
+
+    /// update will load the data, perform the specified action, and store the result at the
+    /// given height. Only works on existing values, see Bucket::update
+    pub fn update<A, E>(&mut self, key: &[u8], height: u64, action: A) -> Result<T, E>
+    where
+        A: FnOnce(T) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.load(key)?;
+        let output = action(input)?;
+        self.save(key, &output, height)?;
+        Ok(output)
+    }
+
+    /// may_update works like update, but passes None to the action if there is no data yet
+    pub fn may_update<A, E>(&mut self, key: &[u8], height: u64, action: A) -> Result<T, E>
+    where
+        A: FnOnce(Option<T>) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.may_load(key)?;
+        let output = action(input)?;
+        self.save(key, &output, height)?;
+        Ok(output)
+    }
+
+    /// add_checkpoint allows queries at the given height with the Selected strategy.
+    /// It must be added before any change at that height. Checkpoints are counted,
+    /// so several users can add the same height and remove it independently
+    pub fn add_checkpoint(&mut self, height: u64) -> Result<()> {
+        add_checkpoint(self.storage, &self.namespace, height)
+    }
+
+    /// remove_checkpoint undoes one add_checkpoint at the given height.
+    /// Values recorded for it are kept
+    pub fn remove_checkpoint(&mut self, height: u64) -> Result<()> {
+        remove_checkpoint(self.storage, &self.namespace, height)
+    }
+
+    fn primary(&mut self) -> Bucket<'_, S, T> {
+        Bucket::multilevel(&[&self.namespace, PRIMARY_NAMESPACE], self.storage)
+    }
+
+    fn primary_read(&self) -> ReadonlyBucket<'_, S, T> {
+        ReadonlyBucket::multilevel(&[&self.namespace, PRIMARY_NAMESPACE], self.storage)
+    }
+}
+
+/// ReadonlySnapshotBucket only requires a ReadonlyStorage and exposes only the
+/// methods of SnapshotBucket that don't modify state.
+pub struct ReadonlySnapshotBucket<'a, S: ReadonlyStorage, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    storage: &'a S,
+    namespace: Vec<u8>,
+    strategy: Strategy,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+}
+
+impl<'a, S: ReadonlyStorage, T> ReadonlySnapshotBucket<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(namespace: &[u8], storage: &'a S, strategy: Strategy) -> Self {
+        ReadonlySnapshotBucket {
+            storage,
+            namespace: namespace.to_vec(),
+            strategy,
+            data: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a S, strategy: Strategy) -> Result<Self> {
+        try_key_prefix(namespace)?;
+        Ok(Self::new(namespace, storage, strategy))
+    }
+
+    /// load will return an error if no data is set at the given key, or on parse error
+    pub fn load(&self, key: &[u8]) -> Result<T> {
+        self.primary().load(key)
+    }
+
+    /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self, key: &[u8]) -> Result<Option<T>> {
+        self.primary().may_load(key)
+    }
+
+    /// may_load_at_height returns the value at the beginning of the given height.
+    /// Returns NotCheckpointed if the strategy doesn't allow queries at this height
+    pub fn may_load_at_height(&self, key: &[u8], height: u64) -> Result<Option<T>> {
+        may_load_at_height(self.storage, &self.namespace, self.strategy, key, height)
+    }
+
+    fn primary(&self) -> ReadonlyBucket<'a, S, T> {
+        ReadonlyBucket::multilevel(&[&self.namespace, PRIMARY_NAMESPACE], self.storage)
+    }
+}
+
+// record_change stores the current value of the key in the changelog before it is changed
+// at height, if the strategy requires it
+pub(crate) fn record_change<S: Storage, T>(
+    storage: &mut S,
+    namespace: &[u8],
+    strategy: Strategy,
+    key: &[u8],
+    height: u64,
+) -> Result<()>
+where
+    T: Serialize + DeserializeOwned,
+{
+    // the key is part of the changelog namespace, so keys longer than 0xFFFF bytes are rejected
+    // here, before anything is written
+    let changelog = try_key_prefix_nested(&[namespace, CHANGELOG_NAMESPACE, key])?;
+    let since = match strategy {
+        Strategy::EveryBlock => height,
+        Strategy::Never => return Ok(()),
+        Strategy::Selected => match latest_checkpoint(storage, namespace)? {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(()),
+        },
+    };
+    if has_changes_since(storage, &changelog, since) {
+        // the value needed for queries at this height is already recorded
+        return Ok(());
+    }
+    let old = ReadonlyBucket::<_, T>::multilevel(&[namespace, PRIMARY_NAMESPACE], storage)
+        .may_load(key)?;
+    Bucket::<_, ChangeSet<T>>::try_multilevel(&[namespace, CHANGELOG_NAMESPACE, key], storage)?
+        .save(&height.to_be_bytes(), &ChangeSet { old })
+}
+
+// may_load_at_height returns the value of the key at the beginning of height
+pub(crate) fn may_load_at_height<S: ReadonlyStorage, T>(
+    storage: &S,
+    namespace: &[u8],
+    strategy: Strategy,
+    key: &[u8],
+    height: u64,
+) -> Result<Option<T>>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let height_key = height.to_be_bytes();
+    let queryable = match strategy {
+        Strategy::EveryBlock => true,
+        Strategy::Never => false,
+        Strategy::Selected => {
+            ReadonlyBucket::<_, u32>::multilevel(&[namespace, CHECKPOINTS_NAMESPACE], storage)
+                .may_load(&height_key)?
+                .is_some()
+        }
+    };
+    if !queryable {
+        return NotCheckpointed { height }.fail();
+    }
+
+    // the first change at or after height recorded the value we are looking for.
+    // Without any change since then, the current value is still valid
+    let changelog = ReadonlyBucket::<_, ChangeSet<T>>::try_multilevel(
+        &[namespace, CHANGELOG_NAMESPACE, key],
+        storage,
+    )?;
+    let first = changelog
+        .range(Some(&height_key), None, Order::Ascending)
+        .next();
+    match first {
+        Some(item) => Ok(item?.1.old),
+        None => ReadonlyBucket::<_, T>::multilevel(&[namespace, PRIMARY_NAMESPACE], storage)
+            .may_load(key),
+    }
+}
+
+pub(crate) fn add_checkpoint<S: Storage>(
+    storage: &mut S,
+    namespace: &[u8],
+    height: u64,
+) -> Result<()> {
+    Bucket::<_, u32>::multilevel(&[namespace, CHECKPOINTS_NAMESPACE], storage)
+        .may_update(&height.to_be_bytes(), |count| -> Result<_> {
+            Ok(count.unwrap_or_default() + 1)
+        })?;
+    Ok(())
+}
+
+pub(crate) fn remove_checkpoint<S: Storage>(
+    storage: &mut S,
+    namespace: &[u8],
+    height: u64,
+) -> Result<()> {
+    let key = height.to_be_bytes();
+    let mut checkpoints =
+        Bucket::<_, u32>::multilevel(&[namespace, CHECKPOINTS_NAMESPACE], storage);
+    match checkpoints.may_load(&key)? {
+        Some(count) if count > 1 => checkpoints.save(&key, &(count - 1)),
+        Some(_) => checkpoints.remove(&key),
+        None => Ok(()),
+    }
+}
+
+// latest_checkpoint returns the highest height registered with add_checkpoint
+fn latest_checkpoint<S: ReadonlyStorage>(storage: &S, namespace: &[u8]) -> Result<Option<u64>> {
+    let prefix = key_prefix_nested(&[namespace, CHECKPOINTS_NAMESPACE]);
+    let last = range_with_prefix(storage, &prefix, None, None, Order::Descending).next();
+    match last {
+        Some(kv) => Ok(Some(u64::parse_key(&kv?.0)?)),
+        None => Ok(None),
+    }
+}
+
+// has_changes_since checks if a change was recorded in the changelog of a key at or after
+// the given height
+fn has_changes_since<S: ReadonlyStorage>(storage: &S, changelog: &[u8], height: u64) -> bool {
+    let start = height.to_be_bytes();
+    range_with_prefix(storage, changelog, Some(&start), None, Order::Ascending)
+        .next()
+        .is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+
+    #[test]
+    fn every_block_history() {
+        let mut store = MockStorage::new();
+        let mut power = snapshot_bucket::<_, u64>(b"power", &mut store, Strategy::EveryBlock);
+
+        power.save(b"alice", &10, 5).unwrap();
+        power.save(b"alice", &20, 8).unwrap();
+        // only the value before the first change at a height is recorded
+        power.save(b"alice", &25, 8).unwrap();
+        power.save(b"bob", &7, 8).unwrap();
+        power.remove(b"alice", 12).unwrap();
+
+        assert_eq!(power.may_load(b"alice").unwrap(), None);
+        assert_eq!(power.load(b"bob").unwrap(), 7);
+
+        let expected = vec![
+            (1, None),
+            (5, None),
+            (6, Some(10)),
+            (8, Some(10)),
+            (9, Some(25)),
+            (12, Some(25)),
+            (13, None),
+        ];
+        for (height, value) in expected {
+            assert_eq!(power.may_load_at_height(b"alice", height).unwrap(), value);
+        }
+        assert_eq!(power.may_load_at_height(b"bob", 8).unwrap(), None);
+        assert_eq!(power.may_load_at_height(b"bob", 9).unwrap(), Some(7));
+
+        let reader = snapshot_bucket_read::<_, u64>(b"power", &store, Strategy::EveryBlock);
+        assert_eq!(reader.may_load_at_height(b"alice", 10).unwrap(), Some(25));
+        assert_eq!(reader.may_load(b"bob").unwrap(), Some(7));
+    }
+
+    #[test]
+    fn selected_checkpoints() {
+        let mut store = MockStorage::new();
+        let mut power = snapshot_bucket::<_, u64>(b"power", &mut store, Strategy::Selected);
+
+        // nothing is recorded without checkpoints
+        power.save(b"alice", &10, 1).unwrap();
+        power.add_checkpoint(3).unwrap();
+        power.save(b"alice", &20, 4).unwrap();
+        power.save(b"alice", &30, 5).unwrap();
+        power.add_checkpoint(6).unwrap();
+        power.save(b"alice", &40, 6).unwrap();
+
+        assert_eq!(power.may_load_at_height(b"alice", 3).unwrap(), Some(10));
+        assert_eq!(power.may_load_at_height(b"alice", 6).unwrap(), Some(30));
+        match power.may_load_at_height(b"alice", 5) {
+            Err(StorageError::NotCheckpointed { height: 5 }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // checkpoints are counted
+        power.add_checkpoint(3).unwrap();
+        power.remove_checkpoint(3).unwrap();
+        assert_eq!(power.may_load_at_height(b"alice", 3).unwrap(), Some(10));
+        power.remove_checkpoint(3).unwrap();
+        assert!(power.may_load_at_height(b"alice", 3).is_err());
+    }
+
+    #[test]
+    fn never_records() {
+        let mut store = MockStorage::new();
+        let mut power = snapshot_bucket::<_, u64>(b"power", &mut store, Strategy::Never);
+
+        power.save(b"alice", &10, 1).unwrap();
+        power.save(b"alice", &20, 2).unwrap();
+        assert_eq!(power.load(b"alice").unwrap(), 20);
+        let err = power.may_load_at_height(b"alice", 2).unwrap_err();
+        assert_eq!(err.to_string(), "No snapshot at height 2");
+
+        // only the primary data was written
+        let prefix = key_prefix_nested(&[b"power", CHANGELOG_NAMESPACE]);
+        assert_eq!(
+            range_with_prefix(&store, &prefix, None, None, Order::Ascending).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn update_at_height() {
+        let mut store = MockStorage::new();
+        let mut power = snapshot_bucket::<_, u64>(b"power", &mut store, Strategy::EveryBlock);
+
+        assert!(power
+            .update(b"alice", 1, |p| -> Result<_> { Ok(p + 1) })
+            .is_err());
+        power
+            .may_update(b"alice", 1, |p| -> Result<_> {
+                Ok(p.unwrap_or_default() + 5)
+            })
+            .unwrap();
+        let updated = power
+            .update(b"alice", 3, |p| -> Result<_> { Ok(p * 2) })
+            .unwrap();
+        assert_eq!(updated, 10);
+
+        assert_eq!(power.may_load_at_height(b"alice", 1).unwrap(), None);
+        assert_eq!(power.may_load_at_height(b"alice", 3).unwrap(), Some(5));
+        assert_eq!(power.may_load_at_height(b"alice", 4).unwrap(), Some(10));
+    }
+
+    #[test]
+    fn long_keys_are_rejected() {
+        let mut store = MockStorage::new();
+        let mut power = snapshot_bucket::<_, u64>(b"power", &mut store, Strategy::Never);
+        let long = vec![b'x'; 0x10000];
+
+        let err = power.save(&long, &10, 1).unwrap_err();
+        assert_eq!(err, StorageError::NamespaceTooLong { length: 0x10000 });
+        assert!(power.may_load_at_height(&long, 1).is_err());
+        // nothing was written
+        assert_eq!(power.may_load(&long).unwrap(), None);
+    }
+}
@@ -0,0 +1,289 @@
+use serde::{de::DeserializeOwned, ser::Serialize};
+use std::marker::PhantomData;
+
+use cosmwasm_std::{ReadonlyStorage, Storage};
+
+use crate::errors::{Result, StorageError};
+use crate::namespace_helpers::try_key_prefix;
+use crate::prefix::{PrefixedStorage, ReadonlyPrefixedStorage};
+use crate::singleton::{ReadonlySingleton, Singleton};
+use crate::snapshot::{
+    add_checkpoint, may_load_at_height, record_change, remove_checkpoint, Strategy,
+    PRIMARY_NAMESPACE,
+};
+
+// the singleton is stored like the entry of a SnapshotBucket with an empty key
+const SINGLETON_KEY: &[u8] = b"";
+
+pub fn snapshot_singleton<'a, S: Storage, T>(
+    storage: &'a mut S,
+    key: &[u8],
+    strategy: Strategy,
+) -> SnapshotSingleton<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    SnapshotSingleton::new(storage, key, strategy)
+}
+
+pub fn snapshot_singleton_read<'a, S: ReadonlyStorage, T>(
+    storage: &'a S,
+    key: &[u8],
+    strategy: Strategy,
+) -> ReadonlySnapshotSingleton<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    ReadonlySnapshotSingleton::new(storage, key, strategy)
+}
+
+/// SnapshotSingleton is a Singleton that can be queried at past heights, e.g. the total supply
+/// when a proposal was created. It records changes exactly like SnapshotBucket and uses the same
+/// layout, as a SnapshotBucket under the same name holding only the empty key.
+pub struct SnapshotSingleton<'a, S: Storage, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    storage: &'a mut S,
+    namespace: Vec<u8>,
+    strategy: Strategy,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+}
+
+impl<'a, S: Storage, T> SnapshotSingleton<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(storage: &'a mut S, key: &[u8], strategy: Strategy) -> Self {
+        SnapshotSingleton {
+            storage,
+            namespace: key.to_vec(),
+            strategy,
+            data: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the key is longer than 0xFFFF bytes
+    pub fn try_new(storage: &'a mut S, key: &[u8], strategy: Strategy) -> Result<Self> {
+        try_key_prefix(key)?;
+        Ok(Self::new(storage, key, strategy))
+    }
+
+    /// save stores the value at the given height, recording the previous value if needed
+    pub fn save(&mut self, data: &T, height: u64) -> Result<()> {
+        self.record_change(height)?;
+        let mut store = PrefixedStorage::new(&self.namespace, self.storage);
+        Singleton::<_, T>::new(&mut store, PRIMARY_NAMESPACE).save(data)
+    }
+
+    /// remove deletes the value at the given height, recording the previous value if needed
+    pub fn remove(&mut self, height: u64) -> Result<()> {
+        self.record_change(height)?;
+        let mut store = PrefixedStorage::new(&self.namespace, self.storage);
+        Singleton::<_, T>::new(&mut store, PRIMARY_NAMESPACE).remove()?;
+        Ok(())
+    }
+
+    /// load will return an error if no data is set, or on parse error
+    pub fn load(&self) -> Result<T> {
+        let store = ReadonlyPrefixedStorage::new(&self.namespace, self.storage);
+        ReadonlySingleton::<_, T>::new(&store, PRIMARY_NAMESPACE).load()
+    }
+
+    /// may_load will parse the data if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self) -> Result<Option<T>> {
+        let store = ReadonlyPrefixedStorage::new(&self.namespace, self.storage);
+        ReadonlySingleton::<_, T>::new(&store, PRIMARY_NAMESPACE).may_load()
+    }
+
+    /// may_load_at_height returns the value at the beginning of the given height.
+    /// Returns NotCheckpointed if the strategy doesn't allow queries at this height
+    pub fn may_load_at_height(&self, height: u64) -> Result<Option<T>> {
+        may_load_at_height(
+            self.storage,
+            &self.namespace,
+            self.strategy,
+            SINGLETON_KEY,
+            height,
+        )
+    }
+
+    /// update will load the data, perform the specified action, and store the result at the
+    /// given height. Returns NotFound if the singleton was never saved, see `may_update`
+    pub fn update<A, E>(&mut self, height: u64, action: A) -> Result<T, E>
+    where
+        A: FnOnce(T) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.load()?;
+        let output = action(input)?;
+        self.save(&output, height)?;
+        Ok(output)
+    }
+
+    /// may_update works like update, but passes None to the action if there is no data yet
+    pub fn may_update<A, E>(&mut self, height: u64, action: A) -> Result<T, E>
+    where
+        A: FnOnce(Option<T>) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.may_load()?;
+        let output = action(input)?;
+        self.save(&output, height)?;
+        Ok(output)
+    }
+
+    /// add_checkpoint allows queries at the given height with the Selected strategy.
+    /// See SnapshotBucket::add_checkpoint
+    pub fn add_checkpoint(&mut self, height: u64) -> Result<()> {
+        add_checkpoint(self.storage, &self.namespace, height)
+    }
+
+    /// remove_checkpoint undoes one add_checkpoint at the given height
+    pub fn remove_checkpoint(&mut self, height: u64) -> Result<()> {
+        remove_checkpoint(self.storage, &self.namespace, height)
+    }
+
+    fn record_change(&mut self, height: u64) -> Result<()> {
+        record_change::<_, T>(
+            self.storage,
+            &self.namespace,
+            self.strategy,
+            SINGLETON_KEY,
+            height,
+        )
+    }
+}
+
+/// ReadonlySnapshotSingleton only requires a ReadonlyStorage and exposes only the
+/// methods of SnapshotSingleton that don't modify state.
+pub struct ReadonlySnapshotSingleton<'a, S: ReadonlyStorage, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    storage: &'a S,
+    namespace: Vec<u8>,
+    strategy: Strategy,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+}
+
+impl<'a, S: ReadonlyStorage, T> ReadonlySnapshotSingleton<'a, S, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn new(storage: &'a S, key: &[u8], strategy: Strategy) -> Self {
+        ReadonlySnapshotSingleton {
+            storage,
+            namespace: key.to_vec(),
+            strategy,
+            data: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the key is longer than 0xFFFF bytes
+    pub fn try_new(storage: &'a S, key: &[u8], strategy: Strategy) -> Result<Self> {
+        try_key_prefix(key)?;
+        Ok(Self::new(storage, key, strategy))
+    }
+
+    /// load will return an error if no data is set, or on parse error
+    pub fn load(&self) -> Result<T> {
+        let store = ReadonlyPrefixedStorage::new(&self.namespace, self.storage);
+        ReadonlySingleton::<_, T>::new(&store, PRIMARY_NAMESPACE).load()
+    }
+
+    /// may_load will parse the data if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self) -> Result<Option<T>> {
+        let store = ReadonlyPrefixedStorage::new(&self.namespace, self.storage);
+        ReadonlySingleton::<_, T>::new(&store, PRIMARY_NAMESPACE).may_load()
+    }
+
+    /// may_load_at_height returns the value at the beginning of the given height.
+    /// Returns NotCheckpointed if the strategy doesn't allow queries at this height
+    pub fn may_load_at_height(&self, height: u64) -> Result<Option<T>> {
+        may_load_at_height(
+            self.storage,
+            &self.namespace,
+            self.strategy,
+            SINGLETON_KEY,
+            height,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::snapshot::snapshot_bucket_read;
+    use cosmwasm_std::testing::MockStorage;
+    use cosmwasm_std::Uint128;
+
+    #[test]
+    fn history_of_total_supply() {
+        let mut store = MockStorage::new();
+        let mut supply =
+            snapshot_singleton::<_, Uint128>(&mut store, b"supply", Strategy::EveryBlock);
+
+        assert_eq!(supply.may_load().unwrap(), None);
+        supply.save(&Uint128(1000), 3).unwrap();
+        supply
+            .update(5, |s| -> Result<_> { Ok(s + Uint128(500)) })
+            .unwrap();
+        // the second change at the same height is not recorded
+        supply
+            .update(5, |s| -> Result<_> { Ok((s - Uint128(100))?) })
+            .unwrap();
+        assert_eq!(supply.load().unwrap(), Uint128(1400));
+
+        assert_eq!(supply.may_load_at_height(3).unwrap(), None);
+        assert_eq!(supply.may_load_at_height(4).unwrap(), Some(Uint128(1000)));
+        assert_eq!(supply.may_load_at_height(5).unwrap(), Some(Uint128(1000)));
+        assert_eq!(supply.may_load_at_height(6).unwrap(), Some(Uint128(1400)));
+
+        supply.remove(9).unwrap();
+        let reader = snapshot_singleton_read::<_, Uint128>(&store, b"supply", Strategy::EveryBlock);
+        assert_eq!(reader.may_load().unwrap(), None);
+        assert!(reader.load().is_err());
+        assert_eq!(reader.may_load_at_height(9).unwrap(), Some(Uint128(1400)));
+        assert_eq!(reader.may_load_at_height(10).unwrap(), None);
+    }
+
+    #[test]
+    fn selected_checkpoints() {
+        let mut store = MockStorage::new();
+        let mut supply = snapshot_singleton::<_, u64>(&mut store, b"supply", Strategy::Selected);
+
+        supply
+            .may_update(1, |s| -> Result<_> { Ok(s.unwrap_or_default() + 10) })
+            .unwrap();
+        supply.add_checkpoint(2).unwrap();
+        supply.save(&20, 4).unwrap();
+        supply.save(&30, 5).unwrap();
+
+        assert_eq!(supply.may_load_at_height(2).unwrap(), Some(10));
+        match supply.may_load_at_height(4) {
+            Err(StorageError::NotCheckpointed { height: 4 }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        supply.remove_checkpoint(2).unwrap();
+        assert!(supply.may_load_at_height(2).is_err());
+    }
+
+    #[test]
+    fn shares_layout_with_bucket() {
+        let mut store = MockStorage::new();
+        let mut supply = snapshot_singleton::<_, u64>(&mut store, b"supply", Strategy::EveryBlock);
+        supply.save(&7, 1).unwrap();
+        supply.save(&8, 2).unwrap();
+
+        // the same data can be inspected as a snapshot bucket with an empty key
+        let bucket = snapshot_bucket_read::<_, u64>(b"supply", &store, Strategy::EveryBlock);
+        assert_eq!(bucket.load(b"").unwrap(), 8);
+        assert_eq!(bucket.may_load_at_height(b"", 2).unwrap(), Some(7));
+        assert_eq!(bucket.may_load_at_height(b"", 1).unwrap(), None);
+    }
+}
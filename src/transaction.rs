@@ -0,0 +1,416 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "iterator")]
+use std::iter::Peekable;
+#[cfg(feature = "iterator")]
+use std::ops::Bound;
+
+#[cfg(feature = "iterator")]
+use cosmwasm_std::{Order, KV};
+use cosmwasm_std::{ReadonlyStorage, StdResult, Storage};
+
+use crate::errors::{Result, StorageError};
+
+// transactional runs the given closure against a new transaction on top of storage.
+// If it returns Ok, all writes are committed, otherwise they are discarded
+pub fn transactional<S: Storage, T, A, E>(storage: &mut S, action: A) -> Result<T, E>
+where
+    A: FnOnce(&mut StorageTransaction<S>) -> Result<T, E>,
+    E: From<StorageError>,
+{
+    let mut tx = StorageTransaction::new(storage);
+    let res = action(&mut tx)?;
+    tx.commit().map_err(E::from)?;
+    Ok(res)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Delta {
+    Set { value: Vec<u8> },
+    Delete,
+}
+
+/// StorageTransaction buffers all writes and deletes on top of another storage.
+/// Reads (including range) see the pending changes, while the underlying storage is
+/// only modified once `commit` is called. Dropping the transaction or calling `rollback`
+/// discards all changes.
+///
+/// StorageTransaction implements Storage itself, so it can be wrapped by PrefixedStorage,
+/// Bucket, Singleton, etc. as well as by another StorageTransaction. Committing a nested
+/// transaction writes its changes into the parent transaction.
+pub struct StorageTransaction<'a, S: Storage> {
+    storage: &'a mut S,
+    // local_state holds the latest change for every key written in this transaction
+    local_state: BTreeMap<Vec<u8>, Delta>,
+}
+
+impl<'a, S: Storage> StorageTransaction<'a, S> {
+    pub fn new(storage: &'a mut S) -> Self {
+        StorageTransaction {
+            storage,
+            local_state: BTreeMap::new(),
+        }
+    }
+
+    /// commit writes all pending changes to the underlying storage. If the storage fails,
+    /// the changes written before the error are not undone
+    pub fn commit(self) -> Result<()> {
+        for (key, delta) in self.local_state.into_iter() {
+            match delta {
+                Delta::Set { value } => self.storage.set(&key, &value)?,
+                Delta::Delete => self.storage.remove(&key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// rollback discards all pending changes. This is the same as dropping the transaction,
+    /// but makes the intent explicit
+    pub fn rollback(self) {}
+}
+
+impl<'a, S: Storage> ReadonlyStorage for StorageTransaction<'a, S> {
+    fn get(&self, key: &[u8]) -> StdResult<Option<Vec<u8>>> {
+        match self.local_state.get(key) {
+            Some(Delta::Set { value }) => Ok(Some(value.clone())),
+            Some(Delta::Delete) => Ok(None),
+            None => self.storage.get(key),
+        }
+    }
+
+    #[cfg(feature = "iterator")]
+    fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> StdResult<Box<dyn Iterator<Item = StdResult<KV>> + 'b>> {
+        let bounds = range_bounds(start, end);
+        // BTreeMap::range panics on start > end, while storage just returns nothing
+        let local: LocalIter = match (start, end) {
+            (Some(s), Some(e)) if s >= e => Box::new(std::iter::empty()),
+            _ => match order {
+                Order::Ascending => Box::new(self.local_state.range(bounds)),
+                Order::Descending => Box::new(self.local_state.range(bounds).rev()),
+            },
+        };
+        let base = self.storage.range(start, end, order)?;
+        Ok(Box::new(MergeOverlay::new(base, local, order)))
+    }
+}
+
+impl<'a, S: Storage> Storage for StorageTransaction<'a, S> {
+    fn set(&mut self, key: &[u8], value: &[u8]) -> StdResult<()> {
+        let delta = Delta::Set {
+            value: value.to_vec(),
+        };
+        self.local_state.insert(key.to_vec(), delta);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) -> StdResult<()> {
+        self.local_state.insert(key.to_vec(), Delta::Delete);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "iterator")]
+fn range_bounds(start: Option<&[u8]>, end: Option<&[u8]>) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let start = match start {
+        Some(s) => Bound::Included(s.to_vec()),
+        None => Bound::Unbounded,
+    };
+    let end = match end {
+        Some(e) => Bound::Excluded(e.to_vec()),
+        None => Bound::Unbounded,
+    };
+    (start, end)
+}
+
+#[cfg(feature = "iterator")]
+type LocalIter<'a> = Box<dyn Iterator<Item = (&'a Vec<u8>, &'a Delta)> + 'a>;
+
+// MergeOverlay combines the range over the underlying storage with the pending changes.
+// Both iterators must be sorted in the same order. If a key shows up in both, the local
+// change wins: Set replaces the value, Delete skips the key. Errors of the underlying
+// storage are returned as soon as they show up.
+#[cfg(feature = "iterator")]
+struct MergeOverlay<'a, B: Iterator<Item = StdResult<KV>>> {
+    base: Peekable<B>,
+    local: Peekable<LocalIter<'a>>,
+    order: Order,
+}
+
+#[cfg(feature = "iterator")]
+impl<'a, B: Iterator<Item = StdResult<KV>>> MergeOverlay<'a, B> {
+    fn new(base: B, local: LocalIter<'a>, order: Order) -> Self {
+        MergeOverlay {
+            base: base.peekable(),
+            local: local.peekable(),
+            order,
+        }
+    }
+}
+
+#[cfg(feature = "iterator")]
+impl<'a, B: Iterator<Item = StdResult<KV>>> Iterator for MergeOverlay<'a, B> {
+    type Item = StdResult<KV>;
+
+    fn next(&mut self) -> Option<StdResult<KV>> {
+        loop {
+            let base_first = match (self.base.peek(), self.local.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(Err(_)), Some(_)) => true,
+                (Some(Ok((base_key, _))), Some((local_key, _))) => {
+                    let cmp = match self.order {
+                        Order::Ascending => base_key.as_slice().cmp(local_key.as_slice()),
+                        Order::Descending => local_key.as_slice().cmp(base_key.as_slice()),
+                    };
+                    match cmp {
+                        std::cmp::Ordering::Less => true,
+                        std::cmp::Ordering::Equal => {
+                            // shadowed by the local change
+                            self.base.next();
+                            false
+                        }
+                        std::cmp::Ordering::Greater => false,
+                    }
+                }
+            };
+
+            if base_first {
+                return self.base.next();
+            }
+            match self.local.next() {
+                Some((key, Delta::Set { value })) => return Some(Ok((key.clone(), value.clone()))),
+                // deleted keys are skipped
+                Some((_, Delta::Delete)) => continue,
+                None => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cosmwasm_std::generic_err;
+    use cosmwasm_std::testing::MockStorage;
+    use serde::{Deserialize, Serialize};
+
+    use crate::{bucket, bucket_read, prefixed, prefixed_read, singleton};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Data {
+        pub name: String,
+        pub age: i32,
+    }
+
+    #[test]
+    fn commit_writes_through() {
+        let mut base = MockStorage::new();
+        base.set(b"foo", b"bar").unwrap();
+        base.set(b"food", b"bank").unwrap();
+
+        let mut tx = StorageTransaction::new(&mut base);
+        assert_eq!(tx.get(b"foo").unwrap(), Some(b"bar".to_vec()));
+        tx.set(b"good", b"song").unwrap();
+        tx.set(b"foo", b"baz").unwrap();
+        tx.remove(b"food").unwrap();
+
+        // reads see the pending changes
+        assert_eq!(tx.get(b"good").unwrap(), Some(b"song".to_vec()));
+        assert_eq!(tx.get(b"foo").unwrap(), Some(b"baz".to_vec()));
+        assert_eq!(tx.get(b"food").unwrap(), None);
+        tx.commit().unwrap();
+
+        assert_eq!(base.get(b"good").unwrap(), Some(b"song".to_vec()));
+        assert_eq!(base.get(b"foo").unwrap(), Some(b"baz".to_vec()));
+        assert_eq!(base.get(b"food").unwrap(), None);
+    }
+
+    #[test]
+    fn rollback_discards_changes() {
+        let mut base = MockStorage::new();
+        base.set(b"foo", b"bar").unwrap();
+
+        let mut tx = StorageTransaction::new(&mut base);
+        tx.set(b"foo", b"baz").unwrap();
+        tx.set(b"good", b"song").unwrap();
+        tx.rollback();
+        assert_eq!(base.get(b"foo").unwrap(), Some(b"bar".to_vec()));
+        assert_eq!(base.get(b"good").unwrap(), None);
+
+        // dropping has the same effect
+        {
+            let mut tx = StorageTransaction::new(&mut base);
+            tx.remove(b"foo").unwrap();
+        }
+        assert_eq!(base.get(b"foo").unwrap(), Some(b"bar".to_vec()));
+    }
+
+    #[test]
+    fn nested_transactions() {
+        let mut base = MockStorage::new();
+        base.set(b"foo", b"bar").unwrap();
+
+        let mut outer = StorageTransaction::new(&mut base);
+        outer.set(b"outer", b"1").unwrap();
+
+        let mut inner = StorageTransaction::new(&mut outer);
+        assert_eq!(inner.get(b"outer").unwrap(), Some(b"1".to_vec()));
+        inner.set(b"inner", b"2").unwrap();
+        inner.remove(b"foo").unwrap();
+        inner.rollback();
+        assert_eq!(outer.get(b"inner").unwrap(), None);
+        assert_eq!(outer.get(b"foo").unwrap(), Some(b"bar".to_vec()));
+
+        let mut inner = StorageTransaction::new(&mut outer);
+        inner.set(b"inner", b"3").unwrap();
+        inner.remove(b"foo").unwrap();
+        inner.commit().unwrap();
+        assert_eq!(outer.get(b"inner").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(outer.get(b"foo").unwrap(), None);
+        // nothing reached the base yet
+        outer.commit().unwrap();
+
+        assert_eq!(base.get(b"outer").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(base.get(b"inner").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(base.get(b"foo").unwrap(), None);
+    }
+
+    #[test]
+    fn works_with_prefix_and_bucket() {
+        let mut base = MockStorage::new();
+
+        let mut tx = StorageTransaction::new(&mut base);
+        prefixed(b"foo", &mut tx).set(b"bar", b"gotcha").unwrap();
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        bucket::<_, Data>(b"people", &mut tx)
+            .save(b"maria", &data)
+            .unwrap();
+        singleton::<_, i32>(&mut tx, b"counter").save(&17).unwrap();
+        assert_eq!(
+            bucket_read::<_, Data>(b"people", &tx)
+                .load(b"maria")
+                .unwrap(),
+            data
+        );
+        tx.commit().unwrap();
+
+        assert_eq!(
+            prefixed_read(b"foo", &base).get(b"bar").unwrap(),
+            Some(b"gotcha".to_vec())
+        );
+        assert_eq!(
+            bucket_read::<_, Data>(b"people", &base)
+                .load(b"maria")
+                .unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn transactional_commits_on_success() {
+        let mut base = MockStorage::new();
+        base.set(b"foo", b"bar").unwrap();
+
+        let res = transactional(&mut base, |tx| -> Result<_> {
+            tx.set(b"foo", b"baz").unwrap();
+            Ok(5)
+        });
+        assert_eq!(res.unwrap(), 5);
+        assert_eq!(base.get(b"foo").unwrap(), Some(b"baz".to_vec()));
+
+        // any error type works, e.g. the cosmwasm error
+        let res = transactional(&mut base, |tx| -> cosmwasm_std::StdResult<()> {
+            tx.set(b"foo", b"lost").unwrap();
+            tx.set(b"other", b"lost").unwrap();
+            Err(generic_err("oops"))
+        });
+        assert!(res.is_err());
+        assert_eq!(base.get(b"foo").unwrap(), Some(b"baz".to_vec()));
+        assert_eq!(base.get(b"other").unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_merges_changes() {
+        let mut base = MockStorage::new();
+        base.set(b"bar", b"1").unwrap();
+        base.set(b"foo", b"2").unwrap();
+        base.set(b"moo", b"3").unwrap();
+
+        let mut tx = StorageTransaction::new(&mut base);
+        tx.set(b"apple", b"a").unwrap();
+        tx.set(b"foo", b"b").unwrap();
+        tx.remove(b"moo").unwrap();
+        tx.set(b"zoo", b"c").unwrap();
+        // removing a key never written is fine
+        tx.remove(b"nope").unwrap();
+
+        let all: StdResult<Vec<KV>> = tx.range(None, None, Order::Ascending).unwrap().collect();
+        let expected = vec![
+            (b"apple".to_vec(), b"a".to_vec()),
+            (b"bar".to_vec(), b"1".to_vec()),
+            (b"foo".to_vec(), b"b".to_vec()),
+            (b"zoo".to_vec(), b"c".to_vec()),
+        ];
+        assert_eq!(all.unwrap(), expected);
+
+        let reversed: StdResult<Vec<KV>> =
+            tx.range(None, None, Order::Descending).unwrap().collect();
+        let mut expected_rev = expected.clone();
+        expected_rev.reverse();
+        assert_eq!(reversed.unwrap(), expected_rev);
+
+        // bounds apply to both sides
+        let some: StdResult<Vec<KV>> = tx
+            .range(Some(b"b"), Some(b"n"), Order::Ascending)
+            .unwrap()
+            .collect();
+        assert_eq!(some.unwrap(), expected[1..3].to_vec());
+        let some: StdResult<Vec<KV>> = tx
+            .range(Some(b"b"), Some(b"n"), Order::Descending)
+            .unwrap()
+            .collect();
+        assert_eq!(
+            some.unwrap(),
+            vec![expected[2].clone(), expected[1].clone()]
+        );
+
+        // invalid bounds return nothing
+        assert_eq!(
+            tx.range(Some(b"z"), Some(b"a"), Order::Ascending)
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_in_nested_prefix() {
+        let mut base = MockStorage::new();
+        prefixed(b"ns", &mut base).set(b"a", b"1").unwrap();
+        prefixed(b"ns", &mut base).set(b"b", b"2").unwrap();
+
+        let mut outer = StorageTransaction::new(&mut base);
+        prefixed(b"ns", &mut outer).set(b"c", b"3").unwrap();
+        let mut inner = StorageTransaction::new(&mut outer);
+        prefixed(b"ns", &mut inner).remove(b"a").unwrap();
+        prefixed(b"other", &mut inner).set(b"x", b"4").unwrap();
+
+        let store = prefixed_read(b"ns", &inner);
+        let keys: Vec<Vec<u8>> = store
+            .range(None, None, Order::Ascending)
+            .unwrap()
+            .map(|kv| kv.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![b"b".to_vec(), b"c".to_vec()]);
+    }
+}
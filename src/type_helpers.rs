@@ -1,9 +1,12 @@
 use serde::{de::DeserializeOwned, ser::Serialize};
-use snafu::ResultExt;
 use std::any::type_name;
 
-use cosmwasm::errors::{NotFound, ParseErr, Result, SerializeErr};
-use cosmwasm::serde::{from_slice, to_vec};
+use cosmwasm_std::{from_slice, to_vec, StdError};
+#[cfg(feature = "iterator")]
+use cosmwasm_std::{StdResult, KV};
+
+use crate::codec::Codec;
+use crate::errors::{NotFound, ParseErr, Result, SerializeErr};
 
 // how we can make these names simpler if so desired
 //fn short_type_name<T>() -> &'static str {
@@ -11,30 +14,50 @@ use cosmwasm::serde::{from_slice, to_vec};
 //    long.rsplit("::").next().unwrap_or(long)
 //}
 
-/// serialize makes json bytes, but returns a cosmwasm::Error
+/// serialize makes json bytes, but returns a StorageError
 pub fn serialize<T: Serialize>(data: &T) -> Result<Vec<u8>> {
-    to_vec(data).context(SerializeErr {
-        kind: type_name::<T>(),
+    to_vec(data).or_else(|e| {
+        SerializeErr {
+            kind: type_name::<T>(),
+            msg: std_msg(e),
+        }
+        .fail()
     })
 }
 
-/// may_deserialize parses json bytes from storage (Option), returning Ok(None) if no data present
+/// may_deserialize parses bytes from storage (Option) with the given codec, returning Ok(None) if no data present
 ///
 /// value is an odd type, but this is meant to be easy to use with output from storage.get (Option<Vec<u8>>)
 /// and value.map(|s| s.as_slice()) seems trickier than &value
-pub(crate) fn may_deserialize<T: DeserializeOwned>(value: &Option<Vec<u8>>) -> Result<Option<T>> {
+///
+/// namespace and key are only used to report which entry could not be parsed
+pub(crate) fn may_deserialize<C: Codec, T: DeserializeOwned>(
+    namespace: &[u8],
+    key: &[u8],
+    value: &Option<Vec<u8>>,
+) -> Result<Option<T>> {
     match value {
-        Some(d) => Ok(Some(deserialize(d.as_slice())?)),
+        Some(d) => match C::decode(d.as_slice()) {
+            Ok(t) => Ok(Some(t)),
+            Err(e) => Err(e.at(namespace, key)),
+        },
         None => Ok(None),
     }
 }
 
-/// must_deserialize parses json bytes from storage (Option), returning NotFound error if no data present
-pub(crate) fn must_deserialize<T: DeserializeOwned>(value: &Option<Vec<u8>>) -> Result<T> {
-    match value {
-        Some(d) => deserialize(&d),
+/// must_deserialize parses bytes from storage (Option) with the given codec, returning NotFound error if no data present.
+/// namespace and key are only used to report which entry was missing or could not be parsed
+pub(crate) fn must_deserialize<C: Codec, T: DeserializeOwned>(
+    namespace: &[u8],
+    key: &[u8],
+    value: &Option<Vec<u8>>,
+) -> Result<T> {
+    match may_deserialize::<C, T>(namespace, key, value)? {
+        Some(t) => Ok(t),
         None => NotFound {
             kind: type_name::<T>(),
+            namespace: namespace.to_vec(),
+            key: key.to_vec(),
         }
         .fail(),
     }
@@ -42,15 +65,45 @@ pub(crate) fn must_deserialize<T: DeserializeOwned>(value: &Option<Vec<u8>>) ->
 
 // deserialize is a reflection of serialize and probably what most people outside the crate expect
 pub fn deserialize<T: DeserializeOwned>(value: &[u8]) -> Result<T> {
-    from_slice(value).context(ParseErr {
-        kind: type_name::<T>(),
+    from_slice(value).or_else(|e| {
+        ParseErr {
+            kind: type_name::<T>(),
+            namespace: vec![],
+            key: vec![],
+            msg: std_msg(e),
+        }
+        .fail()
     })
 }
 
+// std_msg returns the message of a cosmwasm parse or serialize error without the type name,
+// which the StorageError holds already
+fn std_msg(err: StdError) -> String {
+    match err {
+        StdError::ParseErr { msg, .. } | StdError::SerializeErr { msg, .. } => msg,
+        err => err.to_string(),
+    }
+}
+
+/// deserialize_kv parses the value of a raw (key, value) pair from storage, keeping the key as is.
+/// Storage errors are passed on. namespace is only used for error messages
+#[cfg(feature = "iterator")]
+pub(crate) fn deserialize_kv<C: Codec, T: DeserializeOwned>(
+    namespace: &[u8],
+    kv: StdResult<KV>,
+) -> Result<(Vec<u8>, T)> {
+    let (k, v) = kv?;
+    match C::decode::<T>(&v) {
+        Ok(t) => Ok((k, t)),
+        Err(e) => Err(e.at(namespace, &k)),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use cosmwasm::errors::Error;
+    use crate::codec::Json;
+    use crate::errors::StorageError;
     use serde::{Deserialize, Serialize};
 
     #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -71,26 +124,53 @@ mod test {
 
         //        let parsed: Data = deserialize(loaded.map(|s| s.as_slice())).unwrap();
         //        assert_eq!(parsed, data);
-        let parsed: Data = must_deserialize(&loaded).unwrap();
+        let parsed: Data = must_deserialize::<Json, _>(b"", b"maria", &loaded).unwrap();
         assert_eq!(parsed, data);
 
-        let may_parse: Option<Data> = may_deserialize(&loaded).unwrap();
+        let may_parse: Option<Data> = may_deserialize::<Json, _>(b"", b"maria", &loaded).unwrap();
         assert_eq!(may_parse, Some(data));
     }
 
     #[test]
     fn handle_none() {
-        let may_parse = may_deserialize::<Data>(&None).unwrap();
+        let may_parse = may_deserialize::<Json, Data>(b"", b"maria", &None).unwrap();
         assert_eq!(may_parse, None);
 
-        let parsed = must_deserialize::<Data>(&None);
+        let parsed = must_deserialize::<Json, Data>(b"\x00\x04data", b"maria", &None);
         match parsed {
             // if we used short_type_name, this would just be Data
-            Err(Error::NotFound { kind }) => {
-                assert_eq!(kind, "cw_storage::type_helpers::test::Data")
+            Err(StorageError::NotFound {
+                kind,
+                namespace,
+                key,
+            }) => {
+                assert_eq!(kind, "cw_storage::type_helpers::test::Data");
+                assert_eq!(namespace, b"\x00\x04data".to_vec());
+                assert_eq!(key, b"maria".to_vec());
             }
             Err(e) => panic!("Unexpected error {}", e),
             Ok(_) => panic!("should error"),
         }
     }
+
+    #[test]
+    fn parse_errors() {
+        let parsed = deserialize::<Data>(b"{\"name\":42}");
+        match parsed {
+            Err(StorageError::ParseErr { kind, .. }) => {
+                assert_eq!(kind, "cw_storage::type_helpers::test::Data")
+            }
+            _ => panic!("Unexpected result {:?}", parsed),
+        }
+
+        // the location is added when reading from storage
+        let value = Some(b"{\"name\":42}".to_vec());
+        let parsed = may_deserialize::<Json, Data>(b"\x00\x04data", b"maria", &value);
+        match parsed {
+            Err(e @ StorageError::ParseErr { .. }) => assert!(e.to_string().starts_with(
+                "Error parsing cw_storage::type_helpers::test::Data in data at maria: "
+            )),
+            _ => panic!("Unexpected result {:?}", parsed),
+        }
+    }
 }
@@ -1,69 +1,85 @@
 use serde::{de::DeserializeOwned, ser::Serialize};
 use std::marker::PhantomData;
 
-use cosmwasm::errors::Result;
-use cosmwasm::traits::{ReadonlyStorage, Storage};
+use cosmwasm_std::{ReadonlyStorage, Storage};
 
-use crate::type_helpers::{may_deserialize, must_deserialize, serialize};
+use crate::codec::{Codec, Json};
+use crate::errors::{Result, StorageError};
+use crate::type_helpers::{may_deserialize, must_deserialize};
 
-pub fn typed<S: Storage, T>(storage: &mut S) -> TypedStorage<S, T>
+pub fn typed<S: Storage, T>(storage: &mut S) -> TypedStorage<'_, S, T>
 where
     T: Serialize + DeserializeOwned,
 {
     TypedStorage::new(storage)
 }
 
-pub fn typed_read<S: ReadonlyStorage, T>(storage: &S) -> ReadonlyTypedStorage<S, T>
+pub fn typed_read<S: ReadonlyStorage, T>(storage: &S) -> ReadonlyTypedStorage<'_, S, T>
 where
     T: Serialize + DeserializeOwned,
 {
     ReadonlyTypedStorage::new(storage)
 }
 
-pub struct TypedStorage<'a, S: Storage, T>
+pub struct TypedStorage<'a, S: Storage, T, C = Json>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     storage: &'a mut S,
     // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
     data: PhantomData<&'a T>,
+    codec: PhantomData<C>,
 }
 
-impl<'a, S: Storage, T> TypedStorage<'a, S, T>
+impl<'a, S: Storage, T, C> TypedStorage<'a, S, T, C>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     pub fn new(storage: &'a mut S) -> Self {
         TypedStorage {
             storage,
             data: PhantomData,
+            codec: PhantomData,
         }
     }
 
     /// save will serialize the model and store, returns an error on serialization issues
     pub fn save(&mut self, key: &[u8], data: &T) -> Result<()> {
-        self.storage.set(key, &serialize(data)?);
+        self.storage.set(key, &C::encode(data)?)?;
+        Ok(())
+    }
+
+    /// remove will delete the data stored at the key. Removing a missing key is a no-op
+    pub fn remove(&mut self, key: &[u8]) -> Result<()> {
+        self.storage.remove(key)?;
         Ok(())
     }
 
     /// load will return an error if no data is set at the given key, or on parse error
     pub fn load(&self, key: &[u8]) -> Result<T> {
-        let value = self.storage.get(key);
-        must_deserialize(&value)
+        let value = self.storage.get(key)?;
+        must_deserialize::<C, _>(b"", key, &value)
     }
 
     /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
     /// returns an error on issues parsing
     pub fn may_load(&self, key: &[u8]) -> Result<Option<T>> {
-        let value = self.storage.get(key);
-        may_deserialize(&value)
+        let value = self.storage.get(key)?;
+        may_deserialize::<C, _>(b"", key, &value)
     }
 
     /// update will load the data, perform the specified action, and store the result
-    /// in the database. This is shorthand for some common sequences, which may be useful
+    /// in the database. This is shorthand for some common sequences, which may be useful.
+    /// The action receives None if there is no data at the key
     ///
     /// This is the least stable of the APIs, and definitely needs some usage
-    pub fn update(&mut self, key: &[u8], action: &dyn Fn(Option<T>) -> Result<T>) -> Result<T> {
+    pub fn update<A, E>(&mut self, key: &[u8], action: A) -> Result<T, E>
+    where
+        A: FnOnce(Option<T>) -> Result<T, E>,
+        E: From<StorageError>,
+    {
         let input = self.may_load(key)?;
         let output = action(input)?;
         self.save(key, &output)?;
@@ -71,49 +87,52 @@ where
     }
 }
 
-pub struct ReadonlyTypedStorage<'a, S: ReadonlyStorage, T>
+pub struct ReadonlyTypedStorage<'a, S: ReadonlyStorage, T, C = Json>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     storage: &'a S,
     // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
     data: PhantomData<&'a T>,
+    codec: PhantomData<C>,
 }
 
-impl<'a, S: ReadonlyStorage, T> ReadonlyTypedStorage<'a, S, T>
+impl<'a, S: ReadonlyStorage, T, C> ReadonlyTypedStorage<'a, S, T, C>
 where
     T: Serialize + DeserializeOwned,
+    C: Codec,
 {
     pub fn new(storage: &'a S) -> Self {
         ReadonlyTypedStorage {
             storage,
             data: PhantomData,
+            codec: PhantomData,
         }
     }
 
     /// load will return an error if no data is set at the given key, or on parse error
     pub fn load(&self, key: &[u8]) -> Result<T> {
-        let value = self.storage.get(key);
-        must_deserialize(&value)
+        let value = self.storage.get(key)?;
+        must_deserialize::<C, _>(b"", key, &value)
     }
 
     /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
     /// returns an error on issues parsing
     pub fn may_load(&self, key: &[u8]) -> Result<Option<T>> {
-        let value = self.storage.get(key);
-        may_deserialize(&value)
+        let value = self.storage.get(key)?;
+        may_deserialize::<C, _>(b"", key, &value)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use cosmwasm::errors::{contract_err, NotFound};
-    use cosmwasm::mock::MockStorage;
+    use cosmwasm_std::generic_err;
+    use cosmwasm_std::testing::MockStorage;
     use serde::{Deserialize, Serialize};
-    use snafu::OptionExt;
 
-    use crate::prefixed;
+    use crate::{prefixed, prefixed_read};
 
     #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
     struct Data {
@@ -142,6 +161,27 @@ mod test {
         assert_eq!(data, loaded);
     }
 
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn custom_codec() {
+        use crate::Bincode;
+        use cosmwasm_std::ReadonlyStorage;
+
+        let mut store = MockStorage::new();
+        let mut bucket = TypedStorage::<_, Data, Bincode>::new(&mut store);
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+        bucket.save(b"maria", &data).unwrap();
+        assert_eq!(bucket.load(b"maria").unwrap(), data);
+
+        // 8 byte string length, 5 bytes name, 4 bytes age
+        assert_eq!(store.get(b"maria").unwrap().unwrap().len(), 17);
+        let reader = ReadonlyTypedStorage::<_, Data, Bincode>::new(&store);
+        assert_eq!(reader.load(b"maria").unwrap(), data);
+    }
+
     #[test]
     fn store_with_prefix() {
         let mut store = MockStorage::new();
@@ -160,6 +200,33 @@ mod test {
         assert_eq!(data, loaded);
     }
 
+    #[test]
+    fn remove_with_prefix() {
+        let mut store = MockStorage::new();
+        let data = Data {
+            name: "Maria".to_string(),
+            age: 42,
+        };
+
+        let mut space = prefixed(b"data", &mut store);
+        let mut bucket = typed::<_, Data>(&mut space);
+        bucket.save(b"maria", &data).unwrap();
+
+        let mut other_space = prefixed(b"dat", &mut store);
+        let mut other = typed::<_, Data>(&mut other_space);
+        other.save(b"amaria", &data).unwrap();
+
+        let mut space = prefixed(b"data", &mut store);
+        let mut bucket = typed::<_, Data>(&mut space);
+        bucket.remove(b"maria").unwrap();
+        assert_eq!(bucket.may_load(b"maria").unwrap(), None);
+
+        // the neighbouring namespace is untouched
+        let other_space = prefixed_read(b"dat", &store);
+        let other = typed_read::<_, Data>(&other_space);
+        assert_eq!(other.load(b"amaria").unwrap(), data);
+    }
+
     #[test]
     fn readonly_works() {
         let mut store = MockStorage::new();
@@ -172,7 +239,7 @@ mod test {
         };
         bucket.save(b"maria", &data).unwrap();
 
-        let reader = typed_read::<_, Data>(&mut store);
+        let reader = typed_read::<_, Data>(&store);
 
         // check empty data handling
         assert!(reader.load(b"john").is_err());
@@ -197,11 +264,11 @@ mod test {
 
         // it's my birthday (fail if no data)
         let birthday = |mayd: Option<Data>| -> Result<Data> {
-            let mut d = mayd.context(NotFound { kind: "Data" })?;
+            let mut d = mayd.expect("no data");
             d.age += 1;
             Ok(d)
         };
-        let output = bucket.update(b"maria", &birthday).unwrap();
+        let output = bucket.update(b"maria", birthday).unwrap();
         let expected = Data {
             name: "Maria".to_string(),
             age: 43,
@@ -226,7 +293,7 @@ mod test {
         bucket.save(b"maria", &init).unwrap();
 
         // it's my birthday
-        let output = bucket.update(b"maria", &|_d| contract_err("cuz i feel like it"));
+        let output = bucket.update(b"maria", |_d| Err(generic_err("cuz i feel like it")));
         assert!(output.is_err());
 
         // load it properly
@@ -246,8 +313,8 @@ mod test {
 
         // it's my birthday
         let output = bucket
-            .update(b"maria", &|d| match d {
-                Some(_) => contract_err("Ensure this was empty"),
+            .update(b"maria", |d| match d {
+                Some(_) => Err(generic_err("Ensure this was empty")),
                 None => Ok(init_value.clone()),
             })
             .unwrap();
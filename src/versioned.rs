@@ -0,0 +1,573 @@
+use serde::{de::DeserializeOwned, ser::Serialize};
+use std::any::type_name;
+use std::marker::PhantomData;
+
+#[cfg(feature = "iterator")]
+use cosmwasm_std::Order;
+use cosmwasm_std::{ReadonlyStorage, Storage};
+
+use crate::codec::{Codec, Json};
+use crate::errors::{NotFound, ParseErr, Result, StorageError, UnsupportedVersion};
+#[cfg(feature = "iterator")]
+use crate::namespace_helpers::range_with_prefix;
+use crate::namespace_helpers::{
+    get_with_prefix, key_prefix, key_prefix_nested, remove_with_prefix, set_with_prefix,
+    try_key_prefix, try_key_prefix_nested,
+};
+
+// Versioned values start with this marker followed by the version (u16 big endian) and the
+// payload encoded with the codec. Json never starts with a 0 byte, so values stored before
+// versioning was enabled can be told apart and are read as version 0. Binary codecs may start
+// with a 0 byte, so with those all values must carry the header
+const VERSION_MARKER: u8 = 0;
+const HEADER_LEN: usize = 3;
+
+/// Migrate is implemented by types stored in a VersionedBucket or VersionedSingleton.
+///
+/// Every value is saved together with the current VERSION. When a value with an older version
+/// is loaded, it is passed to migrate, which parses the old schema and upgrades it. This allows
+/// changing the type without rewriting all stored entries at once.
+pub trait Migrate: Serialize + DeserializeOwned {
+    /// VERSION of the current schema, must be at least 1. Version 0 stands for values that were
+    /// stored as plain json without version, e.g. by a Bucket before switching to VersionedBucket.
+    /// This is only supported with the Json codec, other codecs return a ParseErr for such values
+    const VERSION: u16;
+
+    /// migrate parses a payload stored with an older version, using the codec C, and converts it
+    /// into the current schema. Return StorageError::UnsupportedVersion for unknown versions
+    fn migrate<C: Codec>(version: u16, payload: &[u8]) -> Result<Self>;
+}
+
+// encode_versioned serializes the value with its current version
+pub(crate) fn encode_versioned<C: Codec, T: Migrate>(data: &T) -> Result<Vec<u8>> {
+    let mut value = Vec::with_capacity(HEADER_LEN);
+    value.push(VERSION_MARKER);
+    value.extend_from_slice(&T::VERSION.to_be_bytes());
+    value.extend_from_slice(&C::encode(data)?);
+    Ok(value)
+}
+
+// decode_versioned parses the value, migrating it if needed. Returns the stored version as well
+pub(crate) fn decode_versioned<C: Codec, T: Migrate>(value: &[u8]) -> Result<(u16, T)> {
+    let (version, payload) = if value.len() >= HEADER_LEN && value[0] == VERSION_MARKER {
+        (
+            u16::from_be_bytes([value[1], value[2]]),
+            &value[HEADER_LEN..],
+        )
+    } else if C::NEVER_STARTS_WITH_ZERO {
+        (0, value)
+    } else {
+        return ParseErr {
+            kind: type_name::<T>(),
+            namespace: vec![],
+            key: vec![],
+            msg: "missing version header",
+        }
+        .fail();
+    };
+    let data = if version == T::VERSION {
+        C::decode(payload)?
+    } else if version < T::VERSION {
+        T::migrate::<C>(version, payload)?
+    } else {
+        return UnsupportedVersion {
+            kind: type_name::<T>(),
+            version,
+        }
+        .fail();
+    };
+    Ok((version, data))
+}
+
+// may_decode_versioned is decode_versioned for output of storage.get.
+// namespace and key are only used to report which entry could not be parsed
+pub(crate) fn may_decode_versioned<C: Codec, T: Migrate>(
+    namespace: &[u8],
+    key: &[u8],
+    value: &Option<Vec<u8>>,
+) -> Result<Option<(u16, T)>> {
+    match value {
+        Some(v) => match decode_versioned::<C, T>(v) {
+            Ok(decoded) => Ok(Some(decoded)),
+            Err(e) => Err(e.at(namespace, key)),
+        },
+        None => Ok(None),
+    }
+}
+
+// must_decode_versioned is like may_decode_versioned, but returns NotFound if no data is present
+pub(crate) fn must_decode_versioned<C: Codec, T: Migrate>(
+    namespace: &[u8],
+    key: &[u8],
+    value: &Option<Vec<u8>>,
+) -> Result<T> {
+    match may_decode_versioned::<C, T>(namespace, key, value)? {
+        Some((_, data)) => Ok(data),
+        None => NotFound {
+            kind: type_name::<T>(),
+            namespace: namespace.to_vec(),
+            key: key.to_vec(),
+        }
+        .fail(),
+    }
+}
+
+pub fn versioned_bucket<'a, S: Storage, T>(
+    namespace: &[u8],
+    storage: &'a mut S,
+) -> VersionedBucket<'a, S, T>
+where
+    T: Migrate,
+{
+    VersionedBucket::new(namespace, storage)
+}
+
+pub fn versioned_bucket_read<'a, S: ReadonlyStorage, T>(
+    namespace: &[u8],
+    storage: &'a S,
+) -> ReadonlyVersionedBucket<'a, S, T>
+where
+    T: Migrate,
+{
+    ReadonlyVersionedBucket::new(namespace, storage)
+}
+
+/// VersionedBucket works like a Bucket, but stores the schema version with every value and
+/// migrates older values when they are loaded (see Migrate).
+///
+/// It uses the same keys as a Bucket with the same namespace, so an existing Bucket can be
+/// switched to a VersionedBucket. Its values are read as version 0 (only with the Json codec).
+/// Values are always saved with the current version, use `upgrade` to rewrite a single entry.
+pub struct VersionedBucket<'a, S: Storage, T, C = Json>
+where
+    T: Migrate,
+    C: Codec,
+{
+    storage: &'a mut S,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+    codec: PhantomData<C>,
+    prefix: Vec<u8>,
+}
+
+impl<'a, S: Storage, T, C> VersionedBucket<'a, S, T, C>
+where
+    T: Migrate,
+    C: Codec,
+{
+    pub fn new(namespace: &[u8], storage: &'a mut S) -> Self {
+        VersionedBucket {
+            prefix: key_prefix(namespace),
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    pub fn multilevel(namespaces: &[&[u8]], storage: &'a mut S) -> Self {
+        VersionedBucket {
+            prefix: key_prefix_nested(namespaces),
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a mut S) -> Result<Self> {
+        Ok(VersionedBucket {
+            prefix: try_key_prefix(namespace)?,
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
+    /// try_multilevel works like multilevel, but returns an error if any namespace is longer
+    /// than 0xFFFF bytes
+    pub fn try_multilevel(namespaces: &[&[u8]], storage: &'a mut S) -> Result<Self> {
+        Ok(VersionedBucket {
+            prefix: try_key_prefix_nested(namespaces)?,
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
+    /// save will serialize the model with the current version and store it
+    pub fn save(&mut self, key: &[u8], data: &T) -> Result<()> {
+        set_with_prefix(
+            self.storage,
+            &self.prefix,
+            key,
+            &encode_versioned::<C, T>(data)?,
+        )?;
+        Ok(())
+    }
+
+    /// remove will delete the data stored at the key. Removing a missing key is a no-op
+    pub fn remove(&mut self, key: &[u8]) -> Result<()> {
+        remove_with_prefix(self.storage, &self.prefix, key)?;
+        Ok(())
+    }
+
+    /// load will return an error if no data is set at the given key, or on parse error.
+    /// Older versions are migrated, but not written back
+    pub fn load(&self, key: &[u8]) -> Result<T> {
+        let value = get_with_prefix(self.storage, &self.prefix, key)?;
+        must_decode_versioned::<C, T>(&self.prefix, key, &value)
+    }
+
+    /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self, key: &[u8]) -> Result<Option<T>> {
+        let value = get_with_prefix(self.storage, &self.prefix, key)?;
+        let loaded = may_decode_versioned::<C, T>(&self.prefix, key, &value)?;
+        Ok(loaded.map(|(_, data)| data))
+    }
+
+    /// range iterates over all entries in the bucket, migrating older versions. See Bucket::range
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'b> {
+        range_versioned::<_, T, C>(self.storage, &self.prefix, start, end, order)
+    }
+
+    /// update will load the data, perform the specified action, and store the result
+    /// with the current version. Only works on existing values, see Bucket::update
+    pub fn update<A, E>(&mut self, key: &[u8], action: A) -> Result<T, E>
+    where
+        A: FnOnce(T) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.load(key)?;
+        let output = action(input)?;
+        self.save(key, &output)?;
+        Ok(output)
+    }
+
+    /// may_update works like update, but passes None to the action if there is no data yet
+    pub fn may_update<A, E>(&mut self, key: &[u8], action: A) -> Result<T, E>
+    where
+        A: FnOnce(Option<T>) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.may_load(key)?;
+        let output = action(input)?;
+        self.save(key, &output)?;
+        Ok(output)
+    }
+
+    /// upgrade rewrites the entry with the current version if it was stored with an older one.
+    /// Returns true if the entry was rewritten, false if it was up to date or missing
+    pub fn upgrade(&mut self, key: &[u8]) -> Result<bool> {
+        let value = get_with_prefix(self.storage, &self.prefix, key)?;
+        match may_decode_versioned::<C, T>(&self.prefix, key, &value)? {
+            Some((version, data)) if version != T::VERSION => {
+                self.save(key, &data)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// ReadonlyVersionedBucket only requires a ReadonlyStorage and exposes only the
+/// methods of VersionedBucket that don't modify state.
+pub struct ReadonlyVersionedBucket<'a, S: ReadonlyStorage, T, C = Json>
+where
+    T: Migrate,
+    C: Codec,
+{
+    storage: &'a S,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+    codec: PhantomData<C>,
+    prefix: Vec<u8>,
+}
+
+impl<'a, S: ReadonlyStorage, T, C> ReadonlyVersionedBucket<'a, S, T, C>
+where
+    T: Migrate,
+    C: Codec,
+{
+    pub fn new(namespace: &[u8], storage: &'a S) -> Self {
+        ReadonlyVersionedBucket {
+            prefix: key_prefix(namespace),
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    pub fn multilevel(namespaces: &[&[u8]], storage: &'a S) -> Self {
+        ReadonlyVersionedBucket {
+            prefix: key_prefix_nested(namespaces),
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the namespace is longer than 0xFFFF bytes
+    pub fn try_new(namespace: &[u8], storage: &'a S) -> Result<Self> {
+        Ok(ReadonlyVersionedBucket {
+            prefix: try_key_prefix(namespace)?,
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
+    /// try_multilevel works like multilevel, but returns an error if any namespace is longer
+    /// than 0xFFFF bytes
+    pub fn try_multilevel(namespaces: &[&[u8]], storage: &'a S) -> Result<Self> {
+        Ok(ReadonlyVersionedBucket {
+            prefix: try_key_prefix_nested(namespaces)?,
+            storage,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
+    /// load will return an error if no data is set at the given key, or on parse error.
+    /// Older versions are migrated
+    pub fn load(&self, key: &[u8]) -> Result<T> {
+        let value = get_with_prefix(self.storage, &self.prefix, key)?;
+        must_decode_versioned::<C, T>(&self.prefix, key, &value)
+    }
+
+    /// may_load will parse the data stored at the key if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self, key: &[u8]) -> Result<Option<T>> {
+        let value = get_with_prefix(self.storage, &self.prefix, key)?;
+        let loaded = may_decode_versioned::<C, T>(&self.prefix, key, &value)?;
+        Ok(loaded.map(|(_, data)| data))
+    }
+
+    /// range iterates over all entries in the bucket, migrating older versions. See Bucket::range
+    #[cfg(feature = "iterator")]
+    pub fn range<'b>(
+        &'b self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'b> {
+        range_versioned::<_, T, C>(self.storage, &self.prefix, start, end, order)
+    }
+}
+
+#[cfg(feature = "iterator")]
+fn range_versioned<'a, S: ReadonlyStorage, T, C>(
+    storage: &'a S,
+    prefix: &'a [u8],
+    start: Option<&[u8]>,
+    end: Option<&[u8]>,
+    order: Order,
+) -> Box<dyn Iterator<Item = Result<(Vec<u8>, T)>> + 'a>
+where
+    T: Migrate + 'a,
+    C: Codec,
+{
+    let mapped = range_with_prefix(storage, prefix, start, end, order).map(move |kv| {
+        let (k, v) = kv?;
+        match decode_versioned::<C, T>(&v) {
+            Ok((_, data)) => Ok((k, data)),
+            Err(e) => Err(e.at(prefix, &k)),
+        }
+    });
+    Box::new(mapped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bucket::bucket;
+    use cosmwasm_std::testing::MockStorage;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct PersonV1 {
+        pub name: String,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Person {
+        pub name: String,
+        pub age: u32,
+    }
+
+    impl Migrate for Person {
+        const VERSION: u16 = 2;
+
+        fn migrate<C: Codec>(version: u16, payload: &[u8]) -> Result<Self> {
+            match version {
+                0 | 1 => {
+                    let old: PersonV1 = C::decode(payload)?;
+                    Ok(Person {
+                        name: old.name,
+                        age: 0,
+                    })
+                }
+                _ => UnsupportedVersion {
+                    kind: "Person",
+                    version,
+                }
+                .fail(),
+            }
+        }
+    }
+
+    fn person(name: &str, age: u32) -> Person {
+        Person {
+            name: name.to_string(),
+            age,
+        }
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        let encoded = encode_versioned::<Json, _>(&person("maria", 42)).unwrap();
+        assert_eq!(&encoded[..HEADER_LEN], &[0, 0, 2]);
+        let (version, decoded) = decode_versioned::<Json, Person>(&encoded).unwrap();
+        assert_eq!(version, 2);
+        assert_eq!(decoded, person("maria", 42));
+
+        // newer versions can't be read
+        let future = [&[0u8, 0, 3][..], &encoded[HEADER_LEN..]].concat();
+        match decode_versioned::<Json, Person>(&future) {
+            Err(StorageError::UnsupportedVersion { version: 3, .. }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn binary_codecs_require_header() {
+        use crate::Bincode;
+
+        let encoded = encode_versioned::<Bincode, _>(&person("maria", 42)).unwrap();
+        let (version, decoded) = decode_versioned::<Bincode, Person>(&encoded).unwrap();
+        assert_eq!((version, decoded), (2, person("maria", 42)));
+
+        // unversioned values are not read as version 0, as they may look like a header
+        let legacy = Bincode::encode(&PersonV1 {
+            name: "maria".to_string(),
+        })
+        .unwrap();
+        match decode_versioned::<Bincode, Person>(&legacy) {
+            Err(StorageError::ParseErr { msg, .. }) => assert_eq!(msg, "missing version header"),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn migrate_legacy_bucket() {
+        let mut store = MockStorage::new();
+        // written before the type was versioned
+        let mut legacy = bucket::<_, PersonV1>(b"people", &mut store);
+        legacy
+            .save(
+                b"john",
+                &PersonV1 {
+                    name: "John".to_string(),
+                },
+            )
+            .unwrap();
+        legacy
+            .save(
+                b"maria",
+                &PersonV1 {
+                    name: "Maria".to_string(),
+                },
+            )
+            .unwrap();
+
+        let mut people = versioned_bucket::<_, Person>(b"people", &mut store);
+        assert_eq!(people.load(b"john").unwrap(), person("John", 0));
+
+        // updates are saved with the new version
+        people
+            .update(b"john", |mut p| -> Result<_> {
+                p.age = 33;
+                Ok(p)
+            })
+            .unwrap();
+        assert!(!people.upgrade(b"john").unwrap());
+        assert!(people.upgrade(b"maria").unwrap());
+        assert!(!people.upgrade(b"maria").unwrap());
+        assert!(!people.upgrade(b"nobody").unwrap());
+
+        // the old type can no longer parse upgraded entries
+        assert!(bucket::<_, PersonV1>(b"people", &mut store)
+            .load(b"maria")
+            .is_err());
+
+        let reader = versioned_bucket_read::<_, Person>(b"people", &store);
+        assert_eq!(reader.load(b"john").unwrap(), person("John", 33));
+        assert_eq!(reader.may_load(b"maria").unwrap(), Some(person("Maria", 0)));
+        assert_eq!(reader.may_load(b"nobody").unwrap(), None);
+    }
+
+    #[test]
+    fn errors_show_location() {
+        let mut store = MockStorage::new();
+        bucket::<_, String>(b"people", &mut store)
+            .save(b"bad", &"not a person".to_string())
+            .unwrap();
+
+        let people = versioned_bucket_read::<_, Person>(b"people", &store);
+        let err = people.load(b"bad").unwrap_err();
+        assert!(err.to_string().contains("in people at bad"), "{}", err);
+        let err = people.load(b"missing").unwrap_err();
+        assert!(err.to_string().contains("not found in people at missing"));
+    }
+
+    #[test]
+    fn try_new_rejects_long_namespace() {
+        let mut store = MockStorage::new();
+        let long_namespace = vec![b'a'; 0x10000];
+        assert!(VersionedBucket::<_, Person>::try_new(&long_namespace, &mut store).is_err());
+        let nested: &[&[u8]] = &[b"people", &long_namespace];
+        assert!(VersionedBucket::<_, Person>::try_multilevel(nested, &mut store).is_err());
+        assert!(ReadonlyVersionedBucket::<_, Person>::try_new(&long_namespace, &store).is_err());
+
+        let mut people =
+            VersionedBucket::<_, Person>::try_multilevel(&[b"people", b"2020"], &mut store)
+                .unwrap();
+        people.save(b"john", &person("John", 33)).unwrap();
+        let reader =
+            ReadonlyVersionedBucket::<_, Person>::try_multilevel(&[b"people", b"2020"], &store)
+                .unwrap();
+        assert_eq!(reader.load(b"john").unwrap(), person("John", 33));
+    }
+
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn range_migrates() {
+        let mut store = MockStorage::new();
+        bucket::<_, PersonV1>(b"people", &mut store)
+            .save(
+                b"john",
+                &PersonV1 {
+                    name: "John".to_string(),
+                },
+            )
+            .unwrap();
+        let mut people = versioned_bucket::<_, Person>(b"people", &mut store);
+        people.save(b"maria", &person("Maria", 42)).unwrap();
+
+        let all: Result<Vec<_>> = people.range(None, None, Order::Ascending).collect();
+        assert_eq!(
+            all.unwrap(),
+            vec![
+                (b"john".to_vec(), person("John", 0)),
+                (b"maria".to_vec(), person("Maria", 42)),
+            ]
+        );
+    }
+}
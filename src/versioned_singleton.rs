@@ -0,0 +1,277 @@
+use std::marker::PhantomData;
+
+use cosmwasm_std::{ReadonlyStorage, Storage};
+
+use crate::codec::{Codec, Json};
+use crate::errors::{Result, StorageError};
+use crate::namespace_helpers::{key_prefix, try_key_prefix};
+use crate::versioned::{encode_versioned, may_decode_versioned, must_decode_versioned, Migrate};
+
+pub fn versioned_singleton<'a, S: Storage, T>(
+    storage: &'a mut S,
+    key: &[u8],
+) -> VersionedSingleton<'a, S, T>
+where
+    T: Migrate,
+{
+    VersionedSingleton::new(storage, key)
+}
+
+pub fn versioned_singleton_read<'a, S: ReadonlyStorage, T>(
+    storage: &'a S,
+    key: &[u8],
+) -> ReadonlyVersionedSingleton<'a, S, T>
+where
+    T: Migrate,
+{
+    ReadonlyVersionedSingleton::new(storage, key)
+}
+
+/// VersionedSingleton works like a Singleton, but stores the schema version with the value and
+/// migrates older values when they are loaded (see Migrate).
+///
+/// It uses the same key as a Singleton with the same name, so existing values are read as version 0.
+pub struct VersionedSingleton<'a, S: Storage, T, C = Json>
+where
+    T: Migrate,
+    C: Codec,
+{
+    storage: &'a mut S,
+    key: Vec<u8>,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+    codec: PhantomData<C>,
+}
+
+impl<'a, S: Storage, T, C> VersionedSingleton<'a, S, T, C>
+where
+    T: Migrate,
+    C: Codec,
+{
+    pub fn new(storage: &'a mut S, key: &[u8]) -> Self {
+        VersionedSingleton {
+            storage,
+            key: key_prefix(key),
+            data: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the key is longer than 0xFFFF bytes
+    pub fn try_new(storage: &'a mut S, key: &[u8]) -> Result<Self> {
+        Ok(VersionedSingleton {
+            storage,
+            key: try_key_prefix(key)?,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
+    /// save will serialize the model with the current version and store it
+    pub fn save(&mut self, data: &T) -> Result<()> {
+        self.storage
+            .set(&self.key, &encode_versioned::<C, T>(data)?)?;
+        Ok(())
+    }
+
+    /// remove will delete the stored value. Removing a missing value is a no-op
+    pub fn remove(&mut self) -> Result<()> {
+        self.storage.remove(&self.key)?;
+        Ok(())
+    }
+
+    /// load will return an error if no data is set, or on parse error.
+    /// Older versions are migrated, but not written back
+    pub fn load(&self) -> Result<T> {
+        let value = self.storage.get(&self.key)?;
+        must_decode_versioned::<C, T>(&self.key, b"", &value)
+    }
+
+    /// may_load will parse the data if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self) -> Result<Option<T>> {
+        let value = self.storage.get(&self.key)?;
+        let loaded = may_decode_versioned::<C, T>(&self.key, b"", &value)?;
+        Ok(loaded.map(|(_, data)| data))
+    }
+
+    /// update will load the data, perform the specified action, and store the result
+    /// with the current version. Returns NotFound if the singleton was never saved
+    pub fn update<A, E>(&mut self, action: A) -> Result<T, E>
+    where
+        A: FnOnce(T) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.load()?;
+        let output = action(input)?;
+        self.save(&output)?;
+        Ok(output)
+    }
+
+    /// may_update works like update, but passes None to the action if there is no data yet
+    pub fn may_update<A, E>(&mut self, action: A) -> Result<T, E>
+    where
+        A: FnOnce(Option<T>) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let input = self.may_load()?;
+        let output = action(input)?;
+        self.save(&output)?;
+        Ok(output)
+    }
+
+    /// upgrade rewrites the value with the current version if it was stored with an older one.
+    /// Returns true if the value was rewritten, false if it was up to date or missing
+    pub fn upgrade(&mut self) -> Result<bool> {
+        let value = self.storage.get(&self.key)?;
+        match may_decode_versioned::<C, T>(&self.key, b"", &value)? {
+            Some((version, data)) if version != T::VERSION => {
+                self.save(&data)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// ReadonlyVersionedSingleton only requires a ReadonlyStorage and exposes only the
+/// methods of VersionedSingleton that don't modify state.
+pub struct ReadonlyVersionedSingleton<'a, S: ReadonlyStorage, T, C = Json>
+where
+    T: Migrate,
+    C: Codec,
+{
+    storage: &'a S,
+    key: Vec<u8>,
+    // see https://doc.rust-lang.org/std/marker/struct.PhantomData.html#unused-type-parameters for why this is needed
+    data: PhantomData<&'a T>,
+    codec: PhantomData<C>,
+}
+
+impl<'a, S: ReadonlyStorage, T, C> ReadonlyVersionedSingleton<'a, S, T, C>
+where
+    T: Migrate,
+    C: Codec,
+{
+    pub fn new(storage: &'a S, key: &[u8]) -> Self {
+        ReadonlyVersionedSingleton {
+            storage,
+            key: key_prefix(key),
+            data: PhantomData,
+            codec: PhantomData,
+        }
+    }
+
+    /// try_new works like new, but returns an error if the key is longer than 0xFFFF bytes
+    pub fn try_new(storage: &'a S, key: &[u8]) -> Result<Self> {
+        Ok(ReadonlyVersionedSingleton {
+            storage,
+            key: try_key_prefix(key)?,
+            data: PhantomData,
+            codec: PhantomData,
+        })
+    }
+
+    /// load will return an error if no data is set, or on parse error.
+    /// Older versions are migrated
+    pub fn load(&self) -> Result<T> {
+        let value = self.storage.get(&self.key)?;
+        must_decode_versioned::<C, T>(&self.key, b"", &value)
+    }
+
+    /// may_load will parse the data if present, returns Ok(None) if no data there.
+    /// returns an error on issues parsing
+    pub fn may_load(&self) -> Result<Option<T>> {
+        let value = self.storage.get(&self.key)?;
+        let loaded = may_decode_versioned::<C, T>(&self.key, b"", &value)?;
+        Ok(loaded.map(|(_, data)| data))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::UnsupportedVersion;
+    use crate::singleton::singleton;
+    use cosmwasm_std::testing::MockStorage;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct ConfigV1 {
+        pub owner: String,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Config {
+        pub owner: String,
+        pub max_tokens: i32,
+    }
+
+    impl Migrate for Config {
+        const VERSION: u16 = 1;
+
+        fn migrate<C: Codec>(version: u16, payload: &[u8]) -> Result<Self> {
+            match version {
+                0 => {
+                    let old: ConfigV1 = C::decode(payload)?;
+                    Ok(Config {
+                        owner: old.owner,
+                        max_tokens: 1000,
+                    })
+                }
+                _ => UnsupportedVersion {
+                    kind: "Config",
+                    version,
+                }
+                .fail(),
+            }
+        }
+    }
+
+    #[test]
+    fn migrate_legacy_singleton() {
+        let mut store = MockStorage::new();
+        singleton::<_, ConfigV1>(&mut store, b"config")
+            .save(&ConfigV1 {
+                owner: "admin".to_string(),
+            })
+            .unwrap();
+
+        let mut config = versioned_singleton::<_, Config>(&mut store, b"config");
+        assert_eq!(config.load().unwrap().max_tokens, 1000);
+        assert!(config.upgrade().unwrap());
+        assert!(!config.upgrade().unwrap());
+
+        let updated = config
+            .update(|mut c| -> Result<_> {
+                c.max_tokens += 1;
+                Ok(c)
+            })
+            .unwrap();
+        assert_eq!(updated.max_tokens, 1001);
+
+        let reader = versioned_singleton_read::<_, Config>(&store, b"config");
+        assert_eq!(reader.load().unwrap(), updated);
+        let other = versioned_singleton_read::<_, Config>(&store, b"other");
+        assert_eq!(other.may_load().unwrap(), None);
+    }
+
+    #[test]
+    fn may_update_initializes() {
+        let mut store = MockStorage::new();
+        let mut config = versioned_singleton::<_, Config>(&mut store, b"config");
+        assert!(config.update(Ok::<_, StorageError>).is_err());
+
+        config
+            .may_update(|c| -> Result<_> {
+                assert_eq!(c, None);
+                Ok(Config {
+                    owner: "admin".to_string(),
+                    max_tokens: 5,
+                })
+            })
+            .unwrap();
+        config.remove().unwrap();
+        assert_eq!(config.may_load().unwrap(), None);
+    }
+}